@@ -0,0 +1,397 @@
+use crate::input::Input;
+use crate::parser::{Parser, ParsingExpression, Skipper, TerminalType};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrammarDiagnostic {
+    // used_in is None if the start rule or the skipper rule itself is missing
+    UndefinedRule {
+        rule: String,
+        used_in: Option<String>,
+    },
+    UnreachableRule {
+        rule: String,
+    },
+    DuplicateRule {
+        rule: String,
+    },
+    // e* or e+ where e can match without consuming input, an endless loop
+    NullableRepetition {
+        rule: String,
+        expression: String,
+    },
+    // Rules that can call each other without consuming input
+    LeftRecursion {
+        rules: Vec<String>,
+    },
+}
+
+impl fmt::Display for GrammarDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GrammarDiagnostic::UndefinedRule {
+                rule,
+                used_in: Some(used_in),
+            } => write!(
+                f,
+                "rule {} is used in rule {}, but not defined",
+                rule, used_in
+            ),
+            GrammarDiagnostic::UndefinedRule {
+                rule,
+                used_in: None,
+            } => write!(f, "rule {} is not defined", rule),
+            GrammarDiagnostic::UnreachableRule { rule } => {
+                write!(f, "rule {} can't be reached from the start rule", rule)
+            }
+            GrammarDiagnostic::DuplicateRule { rule } => {
+                write!(f, "rule {} is defined more than once", rule)
+            }
+            GrammarDiagnostic::NullableRepetition { rule, expression } => write!(
+                f,
+                "{} in rule {} repeats an expression that can match nothing",
+                expression, rule
+            ),
+            GrammarDiagnostic::LeftRecursion { rules } => {
+                write!(f, "rules {} are left recursive", rules.join(", "))
+            }
+        }
+    }
+}
+
+impl<'input, T: 'input, I: Input + 'input> Parser<'input, T, I> {
+    /* Checks the grammar without parsing anything: every non-terminal that is reachable from
+     * the start rule must be defined, and every rule should be reachable. Duplicate definitions
+     * that were rejected while building the grammar are reported as well, and so are
+     * repetitions of nullable expressions and, unless it is enabled, left recursion.
+     */
+    pub fn check(&self, start_non_terminal: &str) -> Vec<GrammarDiagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut reached = HashSet::new();
+        let mut undefined = HashSet::new();
+        let mut todo = vec![(start_non_terminal, None)];
+        if let Skipper::Rule(ref skipper) = self.skipper {
+            todo.push((skipper.as_str(), None));
+        }
+
+        while let Some((rule, used_in)) = todo.pop() {
+            if reached.contains(rule) {
+                continue;
+            }
+            match self.rules.get_key_value(rule) {
+                Some((name, definition)) => {
+                    reached.insert(rule);
+                    let mut references = Vec::new();
+                    collect_references(definition.expression.as_ref(), &mut references);
+                    if let Some(ref recover) = definition.recover {
+                        collect_references(recover.as_ref(), &mut references);
+                    }
+                    for reference in references {
+                        todo.push((reference, Some(name.as_str())));
+                    }
+                }
+                None => {
+                    undefined.insert((rule, used_in));
+                }
+            }
+        }
+
+        let mut undefined: Vec<_> = undefined.into_iter().collect();
+        undefined.sort();
+        for (rule, used_in) in undefined {
+            diagnostics.push(GrammarDiagnostic::UndefinedRule {
+                rule: String::from(rule),
+                used_in: used_in.map(String::from),
+            });
+        }
+
+        let mut unreachable: Vec<_> = self
+            .rules
+            .keys()
+            .filter(|rule| !reached.contains(rule.as_str()))
+            .collect();
+        unreachable.sort();
+        for rule in unreachable {
+            diagnostics.push(GrammarDiagnostic::UnreachableRule { rule: rule.clone() });
+        }
+
+        let mut duplicates = self.duplicate_rules.clone();
+        duplicates.sort();
+        duplicates.dedup();
+        for rule in duplicates {
+            diagnostics.push(GrammarDiagnostic::DuplicateRule { rule });
+        }
+
+        for (rule, expression) in self.nullable_repetitions() {
+            diagnostics.push(GrammarDiagnostic::NullableRepetition { rule, expression });
+        }
+
+        if !self.left_recursion {
+            let nullable_rules = self.nullable_rules();
+            for rules in self.left_recursive_rules(&nullable_rules) {
+                diagnostics.push(GrammarDiagnostic::LeftRecursion { rules });
+            }
+        }
+        diagnostics
+    }
+
+    // The rules and dumps of e* and e+ where e is nullable, sorted by rule
+    pub(crate) fn nullable_repetitions(&self) -> Vec<(String, String)> {
+        let nullable_rules = self.nullable_rules();
+        let mut rules: Vec<_> = self.rules.iter().collect();
+        rules.sort_by_key(|(name, _)| name.as_str());
+        let mut repetitions = Vec::new();
+        for (name, rule) in rules {
+            let mut expressions = Vec::new();
+            collect_nullable_repetitions(
+                rule.expression.as_ref(),
+                &nullable_rules,
+                &mut expressions,
+            );
+            for expression in expressions {
+                repetitions.push((name.clone(), expression));
+            }
+        }
+        repetitions
+    }
+
+    /* Rules that can match any amount of input, because a repetition of rules or a recursive
+     * rule can be reached from them. The input a callback of one of them reads may already be discarded.
+     */
+    pub(crate) fn unbounded_rules(&self) -> HashSet<String> {
+        let mut bounded = HashSet::new();
+        self.rules
+            .keys()
+            .filter(|rule| !self.is_bounded(rule, &mut Vec::new(), &mut bounded))
+            .cloned()
+            .collect()
+    }
+
+    // The rules on the path are being checked, a reference back to one of them is a recursion
+    fn is_bounded<'a>(
+        &'a self,
+        rule: &'a str,
+        path: &mut Vec<&'a str>,
+        bounded: &mut HashSet<&'a str>,
+    ) -> bool {
+        if bounded.contains(rule) {
+            return true;
+        }
+        if path.contains(&rule) {
+            return false;
+        }
+        let definition = match self.rules.get(rule) {
+            Some(definition) => definition,
+            None => return true,
+        };
+        if repeats_rules(definition.expression.as_ref()) {
+            return false;
+        }
+        let mut references = Vec::new();
+        collect_references(definition.expression.as_ref(), &mut references);
+        path.push(rule);
+        let result = references
+            .into_iter()
+            .all(|reference| self.is_bounded(reference, path, bounded));
+        path.pop();
+        if result {
+            bounded.insert(rule);
+        }
+        result
+    }
+
+    /* The terminals a match of the rule can start with. None if that isn't known, e.g. because
+     * of a custom expression or an undefined rule.
+     */
+    pub(crate) fn first_terminals(&self, rule: &str) -> Option<Vec<&TerminalType>> {
+        let nullable_rules = self.nullable_rules();
+        let mut terminals = Vec::new();
+        let mut reached = HashSet::new();
+        let mut todo = vec![self.rules.get_key_value(rule)?.0.as_str()];
+        while let Some(rule) = todo.pop() {
+            if reached.insert(rule) {
+                let expression = self.rules.get(rule)?.expression.as_ref();
+                collect_first_terminals(expression, &nullable_rules, &mut terminals, &mut todo)?;
+            }
+        }
+        Some(terminals)
+    }
+
+    // Rules that can succeed without consuming any input
+    pub fn nullable_rules(&self) -> HashSet<&str> {
+        let mut nullable = HashSet::new();
+        loop {
+            let mut changed = false;
+            for (name, rule) in &self.rules {
+                if !nullable.contains(name.as_str()) && rule.expression.is_nullable(&nullable) {
+                    nullable.insert(name.as_str());
+                    changed = true;
+                }
+            }
+            if !changed {
+                return nullable;
+            }
+        }
+    }
+
+    /* Groups of rules that can reach each other at their start position, these are the
+     * strongly connected components (with a cycle) of the "calls at the start" graph.
+     */
+    fn left_recursive_rules(&self, nullable_rules: &HashSet<&str>) -> Vec<Vec<String>> {
+        let mut names: Vec<&str> = self.rules.keys().map(|name| name.as_str()).collect();
+        names.sort_unstable();
+        let mut graph: HashMap<&str, Vec<&str>> = HashMap::new();
+        for name in &names {
+            let mut calls = Vec::new();
+            collect_leftmost_calls(
+                self.rules[*name].expression.as_ref(),
+                nullable_rules,
+                &mut calls,
+            );
+            calls.retain(|call| self.rules.contains_key(*call));
+            graph.insert(name, calls);
+        }
+
+        let mut tarjan = Tarjan {
+            graph: &graph,
+            index: HashMap::new(),
+            low_link: HashMap::new(),
+            stack: Vec::new(),
+            components: Vec::new(),
+        };
+        for name in &names {
+            if !tarjan.index.contains_key(name) {
+                tarjan.visit(name);
+            }
+        }
+
+        let mut cycles: Vec<Vec<String>> = tarjan
+            .components
+            .into_iter()
+            .filter(|component| component.len() > 1 || graph[component[0]].contains(&component[0]))
+            .map(|component| {
+                let mut rules: Vec<String> = component.into_iter().map(String::from).collect();
+                rules.sort();
+                rules
+            })
+            .collect();
+        cycles.sort();
+        cycles
+    }
+}
+
+struct Tarjan<'g, 'a> {
+    graph: &'g HashMap<&'a str, Vec<&'a str>>,
+    index: HashMap<&'a str, usize>,
+    low_link: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    components: Vec<Vec<&'a str>>,
+}
+
+impl<'g, 'a> Tarjan<'g, 'a> {
+    fn visit(&mut self, node: &'a str) {
+        let index = self.index.len();
+        self.index.insert(node, index);
+        self.low_link.insert(node, index);
+        self.stack.push(node);
+
+        for &next in &self.graph[node] {
+            if !self.index.contains_key(next) {
+                self.visit(next);
+                let low = self.low_link[node].min(self.low_link[next]);
+                self.low_link.insert(node, low);
+            } else if self.stack.contains(&next) {
+                let low = self.low_link[node].min(self.index[next]);
+                self.low_link.insert(node, low);
+            }
+        }
+
+        if self.low_link[node] == index {
+            let mut component = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                component.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+fn collect_nullable_repetitions<T, I: Input>(
+    expression: &dyn ParsingExpression<T, I>,
+    nullable_rules: &HashSet<&str>,
+    to: &mut Vec<String>,
+) {
+    let children = expression.children();
+    if expression.is_repetition() && children.iter().any(|c| c.is_nullable(nullable_rules)) {
+        to.push(expression.dump());
+    }
+    for child in children {
+        collect_nullable_repetitions(child, nullable_rules, to);
+    }
+}
+
+// A repetition of rules, like Statement*, can match the whole input. One of terminals is a token
+fn repeats_rules<T, I: Input>(expression: &dyn ParsingExpression<T, I>) -> bool {
+    if expression.is_repetition() {
+        let mut references = Vec::new();
+        collect_references(expression, &mut references);
+        if !references.is_empty() {
+            return true;
+        }
+    }
+    expression.children().into_iter().any(repeats_rules)
+}
+
+fn collect_first_terminals<'a, T, I: Input>(
+    expression: &'a dyn ParsingExpression<T, I>,
+    nullable_rules: &HashSet<&str>,
+    terminals: &mut Vec<&'a TerminalType>,
+    rules: &mut Vec<&'a str>,
+) -> Option<()> {
+    if let Some(terminal) = expression.terminal() {
+        terminals.push(terminal);
+        return Some(());
+    }
+    if let Some(rule) = expression.referenced_rule() {
+        rules.push(rule);
+        return Some(());
+    }
+    let children = expression.leftmost_children(nullable_rules);
+    if children.is_empty() && !expression.is_cut() {
+        // A custom expression, it may match anything
+        return None;
+    }
+    for child in children {
+        collect_first_terminals(child, nullable_rules, terminals, rules)?;
+    }
+    Some(())
+}
+
+fn collect_leftmost_calls<'a, T, I: Input>(
+    expression: &'a dyn ParsingExpression<T, I>,
+    nullable_rules: &HashSet<&str>,
+    to: &mut Vec<&'a str>,
+) {
+    if let Some(rule) = expression.referenced_rule() {
+        to.push(rule);
+    }
+    for child in expression.leftmost_children(nullable_rules) {
+        collect_leftmost_calls(child, nullable_rules, to);
+    }
+}
+
+fn collect_references<'a, T, I: Input>(
+    expression: &'a dyn ParsingExpression<T, I>,
+    to: &mut Vec<&'a str>,
+) {
+    if let Some(rule) = expression.referenced_rule() {
+        to.push(rule);
+    }
+    for child in expression.children() {
+        collect_references(child, to);
+    }
+}
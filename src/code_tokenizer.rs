@@ -1,101 +1,339 @@
-use regex::Regex;
+use crate::input::{Failures, TerminalRegex, Tokenizer};
+use std::cell::OnceCell;
 use std::fmt;
 
 #[derive(fmt::Debug)]
-pub struct CodeTokenizer {
-    code: String,
+pub struct CodeTokenizer<'input> {
+    // Borrowed, the input is never copied. Empty if the input are bytes
+    code: &'input str,
+    // The input as bytes, for text as well
+    bytes: &'input [u8],
+    // False for byte input, which doesn't have to be UTF-8
+    text: bool,
+    // The current byte offset, push_state saves it in states
+    position: usize,
     states: Vec<usize>,
+    failures: Failures,
+    skip_whitespace: bool,
+    // Byte offset of the start of every line, built on the first line/column lookup
+    line_starts: OnceCell<Vec<usize>>,
 }
 
-impl CodeTokenizer {
-    pub fn new(code: &str) -> CodeTokenizer {
-        return CodeTokenizer {
-            code: String::from(code),
-            states: vec![0],
-        };
+impl<'input> CodeTokenizer<'input> {
+    pub fn new(code: &'input str) -> CodeTokenizer<'input> {
+        CodeTokenizer {
+            code,
+            bytes: code.as_bytes(),
+            text: true,
+            position: 0,
+            states: Vec::new(),
+            failures: Failures::default(),
+            skip_whitespace: true,
+            line_starts: OnceCell::new(),
+        }
+    }
+
+    // Binary input: text terminals match their UTF-8 bytes, text regexes never match
+    pub fn from_bytes(bytes: &'input [u8]) -> CodeTokenizer<'input> {
+        CodeTokenizer {
+            code: "",
+            bytes,
+            text: false,
+            ..CodeTokenizer::new("")
+        }
     }
+
     pub fn is_empty(&self) -> bool {
-        let index = *self.states.last().expect("No state left!");
-        self.code.len() <= index
+        self.bytes.len() <= self.position
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.text
+    }
+
+    // Whether match_string and match_regex skip whitespace before and after the match
+    pub fn set_skip_whitespace(&mut self, enabled: bool) {
+        self.skip_whitespace = enabled;
     }
 
     pub fn match_string(&mut self, string: &str) -> bool {
-        if string.is_empty() {
+        self.match_bytes(string.as_bytes())
+    }
+
+    // In text the bytes have to end on a char boundary
+    pub fn match_bytes(&mut self, bytes: &[u8]) -> bool {
+        if bytes.is_empty() {
             return true;
         }
-        if self.code.len() < self.get_state() + string.len() {
-            return false;
+        self.skip_implicit_whitespaces();
+        let end = self.position + bytes.len();
+        if self.bytes[self.position..].starts_with(bytes) && self.is_boundary(end) {
+            self.position = end;
+            self.skip_implicit_whitespaces();
+            true
+        } else {
+            false
         }
-        self.skip_whitespaces();
-        if &self.code[self.get_state()..(self.get_state() + string.len())] == string {
-            *self.states.last_mut().unwrap() += string.len();
-            self.skip_whitespaces();
-            return true;
+    }
+
+    // A single byte from low to high, both inclusive
+    pub fn match_byte_range(&mut self, low: u8, high: u8) -> bool {
+        self.skip_implicit_whitespaces();
+        match self.bytes.get(self.position) {
+            Some(byte) if (low..=high).contains(byte) && self.is_boundary(self.position + 1) => {
+                self.position += 1;
+                self.skip_implicit_whitespaces();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Any count bytes, e.g. a fixed width integer
+    pub fn match_any_bytes(&mut self, count: usize) -> bool {
+        self.skip_implicit_whitespaces();
+        let end = self.position + count;
+        if end <= self.bytes.len() && self.is_boundary(end) {
+            self.position = end;
+            self.skip_implicit_whitespaces();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_boundary(&self, position: usize) -> bool {
+        if self.text {
+            self.code.is_char_boundary(position)
         } else {
+            position <= self.bytes.len()
+        }
+    }
+
+    fn skip_implicit_whitespaces(&mut self) {
+        if self.skip_whitespace {
+            self.skip_whitespaces();
+        }
+    }
+
+    // Skips unicode whitespace, the position stays on a char boundary. Bytes skip ASCII whitespace
+    pub fn skip_whitespaces(&mut self) {
+        if !self.text {
+            let rest = &self.bytes[self.position..];
+            self.position += rest
+                .iter()
+                .take_while(|byte| byte.is_ascii_whitespace())
+                .count();
+            return;
+        }
+        let rest = self.rest();
+        let trimmed = rest.trim_start_matches(char::is_whitespace);
+        self.position += rest.len() - trimmed.len();
+    }
+
+    // The code after the current position
+    fn rest(&self) -> &'input str {
+        &self.code[self.position..]
+    }
+
+    pub fn match_regex(&mut self, regex: &TerminalRegex) -> bool {
+        if !self.text {
             return false;
         }
+        self.skip_implicit_whitespaces();
+        match regex.match_at(self.bytes, self.position) {
+            Some(end) => {
+                self.position = end;
+                self.skip_implicit_whitespaces();
+                true
+            }
+            None => false,
+        }
     }
 
-    fn skip_whitespaces(&mut self) {
-        while let Some(ch) = self.code.chars().nth(self.get_state()) {
-            if ch.is_whitespace() {
-                *self.states.last_mut().unwrap() += 1;
-            } else {
-                break;
+    pub fn match_bytes_regex(&mut self, regex: &TerminalRegex) -> bool {
+        self.skip_implicit_whitespaces();
+        match regex.match_at(self.bytes, self.position) {
+            Some(end) if self.is_boundary(end) => {
+                self.position = end;
+                self.skip_implicit_whitespaces();
+                true
             }
+            _ => false,
         }
     }
 
-    pub fn match_regex(&mut self, regex: &Regex) -> bool {
-        self.skip_whitespaces();
-        match regex.find_at(self.code.as_str(), self.get_state()) {
-            Some(res) => {
-                if self.get_state() != res.start() {
-                    false
-                } else {
-                    *self.states.last_mut().unwrap() += res.range().len();
-                    self.skip_whitespaces();
-                    true
-                }
+    // Moves past the next character or byte, false at the end of the code
+    pub fn skip_char(&mut self) -> bool {
+        if !self.text {
+            let more = self.position < self.bytes.len();
+            if more {
+                self.position += 1;
+            }
+            return more;
+        }
+        match self.rest().chars().next() {
+            Some(ch) => {
+                self.position += ch.len_utf8();
+                true
             }
             None => false,
         }
     }
 
+    /* Saves the current position on a stack: update_state keeps the position and forgets the
+     * saved one, pop_state goes back to it. Misuse never panics, popping more states than were
+     * pushed just keeps the position. ParsingInformation::checkpoint can't be misused at all.
+     */
     pub fn push_state(&mut self) -> usize {
-        self.states
-            .push(self.states.last().expect("No current state!").clone());
-        *self.states.last().unwrap()
+        self.states.push(self.position);
+        self.position
     }
-    /* Takes the topmost value in the stack, saves it, pops it off the stack
-     * and writes it to the new top-entry.
-     */
+
     pub fn update_state(&mut self) -> usize {
-        let current_state = *self.states.last().expect("Nu current state!");
         self.states.pop();
-        let state_below = self
-            .states
-            .last_mut()
-            .expect("No state below the current one!");
-        *state_below = current_state;
-        current_state
+        self.position
     }
+
     pub fn get_state(&self) -> usize {
-        *self.states.last().expect("No state left!")
+        self.position
+    }
+
+    // The state is a byte offset, it has to be on a char boundary of the code
+    pub fn set_state(&mut self, state: usize) {
+        assert!(
+            self.is_boundary(state),
+            "State {} is not a character boundary of the code!",
+            state
+        );
+        self.position = state;
     }
 
     pub fn only_one_state_left(&self) -> bool {
-        self.states.len() == 1
+        self.states.is_empty()
     }
 
     pub fn pop_state(&mut self) {
-        self.states.pop().expect("No state left to pop!");
-        if self.states.is_empty() {
-            panic!("You can't pop the last state!");
+        if let Some(state) = self.states.pop() {
+            self.position = state;
         }
     }
 
-    pub fn get_substr(&self, start: usize, end: usize) -> &str {
+    // Forgets the states pushed after the first count ones, the position stays
+    pub(crate) fn truncate_states(&mut self, count: usize) {
+        self.states.truncate(count);
+    }
+
+    pub(crate) fn saved_states(&self) -> usize {
+        self.states.len()
+    }
+
+    // Only for text, bytes are read with get_bytes
+    pub fn get_substr(&self, start: usize, end: usize) -> &'input str {
+        assert!(self.text, "The input is not text!");
         &self.code[start..end]
     }
+
+    pub fn get_bytes(&self, start: usize, end: usize) -> &'input [u8] {
+        &self.bytes[start..end]
+    }
+
+    /* Line and column (in characters) of a byte offset, both start at 1.
+     * Bytes have no lines, they are always on line 1 and the column is the offset + 1.
+     */
+    pub fn line_column(&self, position: usize) -> (usize, usize) {
+        if !self.text {
+            return (1, position.min(self.bytes.len()) + 1);
+        }
+        let position = position.min(self.code.len());
+        let line = self.line_index(position);
+        let line_start = self.line_starts()[line];
+        (
+            line + 1,
+            self.code[line_start..position].chars().count() + 1,
+        )
+    }
+
+    // The whole line around the offset, empty for bytes
+    pub fn line_at(&self, position: usize) -> &'input str {
+        let line = self.line_index(position.min(self.code.len()));
+        let start = self.line_starts()[line];
+        let end = self
+            .line_starts()
+            .get(line + 1)
+            .map_or(self.code.len(), |next| next - 1);
+        self.code[start..end].trim_end_matches('\r')
+    }
+
+    fn line_starts(&self) -> &[usize] {
+        self.line_starts.get_or_init(|| {
+            let newlines = self.code.match_indices('\n').map(|(i, _)| i + 1);
+            std::iter::once(0).chain(newlines).collect()
+        })
+    }
+
+    // 0-based line of a byte offset
+    fn line_index(&self, position: usize) -> usize {
+        match self.line_starts().binary_search(&position) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        }
+    }
+}
+
+impl<'input> Tokenizer<'input> for CodeTokenizer<'input> {
+    fn get_state(&self) -> usize {
+        CodeTokenizer::get_state(self)
+    }
+    fn set_state(&mut self, state: usize) {
+        CodeTokenizer::set_state(self, state)
+    }
+    fn is_empty(&self) -> bool {
+        CodeTokenizer::is_empty(self)
+    }
+    fn skip_whitespaces(&mut self) {
+        CodeTokenizer::skip_whitespaces(self)
+    }
+    fn skip_char(&mut self) -> bool {
+        CodeTokenizer::skip_char(self)
+    }
+    fn match_string(&mut self, string: &str) -> bool {
+        CodeTokenizer::match_string(self, string)
+    }
+    fn match_regex(&mut self, regex: &TerminalRegex) -> bool {
+        CodeTokenizer::match_regex(self, regex)
+    }
+    fn match_bytes(&mut self, bytes: &[u8]) -> bool {
+        CodeTokenizer::match_bytes(self, bytes)
+    }
+    fn match_byte_range(&mut self, low: u8, high: u8) -> bool {
+        CodeTokenizer::match_byte_range(self, low, high)
+    }
+    fn match_any_bytes(&mut self, count: usize) -> bool {
+        CodeTokenizer::match_any_bytes(self, count)
+    }
+    fn match_bytes_regex(&mut self, regex: &TerminalRegex) -> bool {
+        CodeTokenizer::match_bytes_regex(self, regex)
+    }
+    fn line_column(&self, position: usize) -> (usize, usize) {
+        CodeTokenizer::line_column(self, position)
+    }
+    fn line_at(&self, position: usize) -> &str {
+        CodeTokenizer::line_at(self, position)
+    }
+    fn set_skip_whitespace(&mut self, enabled: bool) {
+        CodeTokenizer::set_skip_whitespace(self, enabled)
+    }
+    fn saved_states(&self) -> usize {
+        CodeTokenizer::saved_states(self)
+    }
+    fn truncate_states(&mut self, count: usize) {
+        CodeTokenizer::truncate_states(self, count)
+    }
+    fn failures(&self) -> &Failures {
+        &self.failures
+    }
+    fn failures_mut(&mut self) -> &mut Failures {
+        &mut self.failures
+    }
 }
@@ -0,0 +1,175 @@
+use crate::input::Tokenizer;
+use std::error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    // The start rule didn't match at all
+    NoMatch,
+    // The start rule matched, but not the whole input
+    UnparsedInput,
+    // The start rule matched, but has no callback that could produce a result
+    MissingCallback(String),
+    // Reading a stream failed, the message of the io::Error
+    Io(String),
+    // The rule is left recursive, but Parser::set_left_recursion wasn't enabled
+    LeftRecursion(String),
+    // A non-terminal or the skipper refers to a rule that isn't defined
+    UndefinedRule(String),
+    /* The rule repeats an expression that can match nothing, e.g. ''*, and would repeat
+     * forever. Grammars with such a rule are rejected before parsing.
+     */
+    EndlessLoop(String),
+    // The offset parsing should start at is past the end of the input or inside a character
+    InvalidOffset(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    // Byte offset of the farthest position the parser failed at
+    pub position: usize,
+    // Terminals and rules that could have matched at that position
+    pub expected: Vec<String>,
+    // Both 1-based, the column is counted in characters (in bytes for byte input)
+    pub line: usize,
+    pub column: usize,
+    pub source_line: String,
+}
+
+impl ParseError {
+    pub(crate) fn new<'input>(
+        kind: ParseErrorKind,
+        position: usize,
+        expected: Vec<String>,
+        tokenizer: &impl Tokenizer<'input>,
+    ) -> Self {
+        let (line, column) = tokenizer.line_column(position);
+        ParseError {
+            kind,
+            position,
+            expected,
+            line,
+            column,
+            source_line: tokenizer.line_at(position).to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::NoMatch => write!(f, "unexpected input")?,
+            ParseErrorKind::UnparsedInput => write!(f, "the input was not parsed completely")?,
+            ParseErrorKind::MissingCallback(rule) => {
+                write!(f, "there is no callback registered for rule {}", rule)?
+            }
+            ParseErrorKind::Io(message) => write!(f, "reading the input failed: {}", message)?,
+            ParseErrorKind::LeftRecursion(rule) => write!(
+                f,
+                "rule {} is left recursive, but left recursion is not enabled",
+                rule
+            )?,
+            ParseErrorKind::UndefinedRule(rule) => write!(f, "rule {} is not defined", rule)?,
+            ParseErrorKind::EndlessLoop(rule) => write!(
+                f,
+                "a repetition in rule {} matched nothing and would repeat forever",
+                rule
+            )?,
+            ParseErrorKind::InvalidOffset(offset) => {
+                write!(f, "offset {} is not a character boundary", offset)?
+            }
+        }
+        write!(f, " at line {}, column {}", self.line, self.column)?;
+        if !self.expected.is_empty() {
+            write!(f, ", expected {}", self.expected.join(" or "))?;
+        }
+        let line_number = self.line.to_string();
+        let padding = " ".repeat(line_number.len());
+        writeln!(f)?;
+        writeln!(f, "{} |", padding)?;
+        writeln!(f, "{} | {}", line_number, self.source_line)?;
+        write!(f, "{} | {}^", padding, " ".repeat(self.column - 1))
+    }
+}
+
+impl error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrammarErrorKind {
+    UnbalancedParentheses,
+    // A quote or bracket that is never closed
+    UnterminatedTerminal,
+    // *, + or ? without an expression in front of it
+    DanglingQuantifier,
+    // ! or & without an expression after it
+    DanglingPredicate,
+    InvalidRegex(String),
+    // An unknown annotation, or one that isn't at the start of the rule
+    InvalidAnnotation(String),
+    // A label without a name or without an expression after it
+    InvalidLabel,
+    // A 'literal' or [regex] in the grammar of an input without text, e.g. Tokens
+    UnsupportedTerminal,
+    DuplicateRule,
+    UndefinedRule,
+    // Text in a grammar document that isn't part of a "Name <- expression" definition
+    ExpectedRuleDefinition,
+    // An empty rule, group or alternative of a choice
+    EmptyAlternative,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrammarError {
+    pub kind: GrammarErrorKind,
+    pub rule: String,
+    // Byte offset into the right side of the rule, or into the whole grammar document
+    pub offset: usize,
+}
+
+impl GrammarError {
+    pub(crate) fn new(kind: GrammarErrorKind, offset: usize) -> Self {
+        GrammarError {
+            kind,
+            rule: String::new(),
+            offset,
+        }
+    }
+
+    pub(crate) fn in_rule(mut self, rule: &str) -> Self {
+        self.rule = String::from(rule);
+        self
+    }
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            GrammarErrorKind::UnbalancedParentheses => write!(f, "unbalanced parentheses")?,
+            GrammarErrorKind::UnterminatedTerminal => write!(f, "unterminated terminal")?,
+            GrammarErrorKind::DanglingQuantifier => write!(f, "quantifier without an expression")?,
+            GrammarErrorKind::DanglingPredicate => write!(f, "predicate without an expression")?,
+            GrammarErrorKind::InvalidRegex(message) => write!(f, "invalid regex: {}", message)?,
+            GrammarErrorKind::InvalidAnnotation(annotation) => {
+                write!(f, "invalid annotation @{}", annotation)?
+            }
+            GrammarErrorKind::InvalidLabel => write!(f, "invalid label")?,
+            GrammarErrorKind::UnsupportedTerminal => {
+                write!(f, "terminals are not supported by the input")?
+            }
+            GrammarErrorKind::DuplicateRule => {
+                return write!(f, "rule {} is already defined", self.rule)
+            }
+            GrammarErrorKind::UndefinedRule => {
+                return write!(f, "rule {} is not defined", self.rule)
+            }
+            GrammarErrorKind::ExpectedRuleDefinition => {
+                return write!(f, "expected a rule definition at offset {}", self.offset)
+            }
+            GrammarErrorKind::EmptyAlternative => write!(f, "empty alternative")?,
+        }
+        write!(f, " in rule {} at offset {}", self.rule, self.offset)
+    }
+}
+
+impl error::Error for GrammarError {}
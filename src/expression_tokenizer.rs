@@ -11,34 +11,45 @@ pub enum ExpressionToken {
     Choice,
     AndPredicate,
     NotPredicate,
-    None, // For ignoring the token
+    Cut,                // ~, commits to the rest of the sequence
+    Label(String),      // name: in front of an expression
+    Annotation(String), // @name, modifies the whole rule
+    None,               // For ignoring the token
 }
 
 #[derive(Debug)]
 pub struct ExpressionTokenizer {
     tokens: Vec<ExpressionToken>,
+    // Byte offset of every token in the token string
+    offsets: Vec<usize>,
     current: usize,
+    length: usize,
+    unterminated: Option<usize>,
 }
 
 impl ExpressionTokenizer {
     pub fn new(tokenstring: &str) -> Self {
         let mut tokenizer = Self {
             tokens: Vec::new(),
+            offsets: Vec::new(),
             current: 0,
+            length: tokenstring.len(),
+            unterminated: None,
         };
         let mut last_string = String::new();
-        let iter = tokenstring.chars().into_iter();
+        let mut last_start = 0;
+        let iter = tokenstring.char_indices();
         let mut last = '\0';
         let mut last_last = '\0'; // NOTE: this is necessary, because of the case \\] in regex parsing
         let mut in_terminal = false;
         let mut terminal_char = '\0';
-        for c in iter {
+        for (offset, c) in iter {
             // last and last_last are needed to prevent \] from getting accepted and to allow \\] getting accepted
             if in_terminal {
                 last_string.push(c);
-                if c == terminal_char && (last != '\\' || (last == last_last && last == '\\')) {
+                if c == terminal_char && (last != '\\' || last_last == '\\') {
                     in_terminal = false;
-                    tokenizer.append_last(last_string);
+                    tokenizer.append_last(last_string, last_start);
                     last_string = String::new();
                 }
             } else {
@@ -59,16 +70,29 @@ impl ExpressionTokenizer {
                     '/' | '|' => Some(ExpressionToken::Choice),
                     '!' => Some(ExpressionToken::NotPredicate),
                     '&' => Some(ExpressionToken::AndPredicate),
+                    '~' => Some(ExpressionToken::Cut),
                     _ if c.is_whitespace() => Some(ExpressionToken::None),
                     _ => None,
                 };
-                if let Some(ex) = expr {
-                    tokenizer.append_last(last_string);
+                if c == ':' {
+                    // The word in front of the colon names the next expression
+                    let label = last_string.trim().to_string();
+                    tokenizer
+                        .offsets
+                        .push(if label.is_empty() { offset } else { last_start });
+                    tokenizer.tokens.push(ExpressionToken::Label(label));
+                    last_string = String::new();
+                } else if let Some(ex) = expr {
+                    tokenizer.append_last(last_string, last_start);
                     last_string = String::new();
                     if ex != ExpressionToken::None {
                         tokenizer.tokens.push(ex);
+                        tokenizer.offsets.push(offset);
                     }
                 } else {
+                    if last_string.is_empty() {
+                        last_start = offset;
+                    }
                     last_string.push(c);
                     last = c;
                     last_last = last;
@@ -76,12 +100,17 @@ impl ExpressionTokenizer {
             }
         }
 
-        tokenizer.append_last(last_string);
-        return tokenizer;
+        if in_terminal {
+            tokenizer.unterminated = Some(last_start);
+        } else {
+            tokenizer.append_last(last_string, last_start);
+        }
+        tokenizer
     }
 
-    fn append_last(&mut self, last_string: String) {
-        if last_string.len() > 0 {
+    fn append_last(&mut self, last_string: String, offset: usize) {
+        if !last_string.is_empty() {
+            self.offsets.push(offset);
             if Self::is_terminal(last_string.as_str()) {
                 if Self::is_regex(last_string.as_str()) {
                     self.tokens.push(ExpressionToken::TerminalRegexExpression(
@@ -92,6 +121,9 @@ impl ExpressionTokenizer {
                         last_string[1..last_string.len() - 1].to_string(),
                     ));
                 }
+            } else if let Some(annotation) = last_string.strip_prefix('@') {
+                self.tokens
+                    .push(ExpressionToken::Annotation(annotation.trim().to_string()));
             } else {
                 self.tokens
                     .push(ExpressionToken::Expression(last_string.trim().to_string()));
@@ -100,7 +132,7 @@ impl ExpressionTokenizer {
     }
 
     pub fn tokens_len(&self) -> usize {
-        return self.tokens.len();
+        self.tokens.len()
     }
 
     pub fn next_token(&mut self) -> Option<ExpressionToken> {
@@ -108,14 +140,25 @@ impl ExpressionTokenizer {
             self.current += 1;
             return Some(self.tokens[self.current - 1].clone());
         }
-        return None;
+        None
     }
 
-    pub fn peek_token(&mut self) -> Option<&ExpressionToken> {
-        if self.current + 1 < self.tokens.len() {
-            return Some(&self.tokens[self.current + 1]);
+    // Offset of the token last returned by next_token, the end of the string before the first one
+    pub fn current_offset(&self) -> usize {
+        if self.current > 0 && self.current <= self.offsets.len() {
+            self.offsets[self.current - 1]
+        } else {
+            self.length
         }
-        return None;
+    }
+
+    // Start of a quoted or bracketed terminal that is never closed
+    pub fn unterminated_terminal(&self) -> Option<usize> {
+        self.unterminated
+    }
+
+    pub fn peek_token(&mut self) -> Option<&ExpressionToken> {
+        self.tokens.get(self.current)
     }
 
     fn is_terminal(expr: &str) -> bool {
@@ -123,11 +166,11 @@ impl ExpressionTokenizer {
         if first == '\'' || first == '\"' || first == '[' {
             return true;
         }
-        return false;
+        false
     }
 
     fn is_regex(expr: &str) -> bool {
         let first = expr.chars().nth(0).unwrap();
-        return first == '[';
+        first == '['
     }
 }
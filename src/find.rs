@@ -0,0 +1,115 @@
+use crate::code_tokenizer::CodeTokenizer;
+use crate::parser::{ParseMode, Parser, Skipper, TerminalType};
+use crate::span::Span;
+
+// A match of Parser::find_iter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<T> {
+    // The matched tokens, without the whitespace skipped around them
+    pub span: Span,
+    // None if the rule has no callback
+    pub result: Option<T>,
+}
+
+/* Tries the rule at every position of the text, like Regex::find_iter. After a match the
+ * search continues behind it, so matches never overlap. Matches without any tokens are skipped.
+ * Positions where none of the terminals a match can start with matches are skipped without
+ * parsing, unless the skipper is a rule.
+ */
+pub struct FindIter<'p, 'input, T> {
+    parser: &'p Parser<'input, T>,
+    rule: String,
+    text: &'input str,
+    tokenizer: CodeTokenizer<'input>,
+    position: usize,
+    done: bool,
+    // The terminals a match can start with and where each of them matches next, if known
+    first_terminals: Option<Vec<(&'p TerminalType, Option<usize>)>>,
+}
+
+impl<'p, 'input, T> FindIter<'p, 'input, T> {
+    // The first position from the current one on a match can start at
+    fn next_candidate(&mut self) -> Option<usize> {
+        let first_terminals = match self.first_terminals {
+            Some(ref mut first_terminals) => first_terminals,
+            None => return Some(self.position),
+        };
+        for (terminal, next) in first_terminals.iter_mut() {
+            if matches!(next, Some(next) if *next < self.position) {
+                *next = find_terminal(terminal, self.text, self.position);
+            }
+        }
+        first_terminals.iter().filter_map(|(_, next)| *next).min()
+    }
+}
+
+// Where the terminal matches next from the position on, None if it doesn't match anymore
+fn find_terminal(terminal: &TerminalType, text: &str, position: usize) -> Option<usize> {
+    match terminal {
+        TerminalType::SIMPLE(literal) => text[position..]
+            .find(literal.as_str())
+            .map(|index| position + index),
+        TerminalType::REGEX(regex) => regex.find_from(text.as_bytes(), position),
+        // Byte terminals are meant for byte input, they are just tried everywhere
+        _ => Some(position),
+    }
+}
+
+impl<'p, 'input, T: 'input> Iterator for FindIter<'p, 'input, T> {
+    type Item = Match<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.next_candidate() {
+                Some(candidate) => self.position = candidate,
+                None => break,
+            }
+            self.tokenizer.set_state(self.position);
+            let rule_result =
+                match self
+                    .parser
+                    .run(&self.rule, &mut self.tokenizer, ParseMode::Callbacks)
+                {
+                    Ok(rule_result) => rule_result,
+                    // E.g. left recursion without Parser::set_left_recursion, it can't match anywhere
+                    Err(_) => break,
+                };
+            let found = rule_result.filter(|res| res.token_start < res.token_end);
+            self.tokenizer.set_state(self.position);
+            self.done = !self.tokenizer.skip_char();
+            self.position = self.tokenizer.get_state();
+            if let Some(res) = found {
+                self.position = self.position.max(res.parsed_string_end);
+                return Some(Match {
+                    span: res.span(),
+                    result: res.rule_result,
+                });
+            }
+        }
+        None
+    }
+}
+
+impl<'input, T: 'input> Parser<'input, T> {
+    pub fn find_iter(&self, rule: &str, text: &'input str) -> FindIter<'_, 'input, T> {
+        // A skipper rule may skip input a terminal matches in, e.g. a comment
+        let first_terminals = match self.skipper {
+            Skipper::None | Skipper::Whitespace => self.first_terminals(rule),
+            Skipper::Rule(_) => None,
+        };
+        FindIter {
+            parser: self,
+            rule: String::from(rule),
+            text,
+            tokenizer: CodeTokenizer::new(text),
+            position: 0,
+            done: false,
+            first_terminals: first_terminals.map(|terminals| {
+                terminals
+                    .into_iter()
+                    .map(|terminal| (terminal, find_terminal(terminal, text, 0)))
+                    .collect()
+            }),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::error::{GrammarError, GrammarErrorKind};
+use crate::input::Input;
+use crate::parser::Parser;
+use regex::Regex;
+
+/* Loads whole grammar documents like
+ *
+ *     # Comments start with a hash
+ *     Sum <- Product (('+' | '-') Product)*
+ *     Product -> Value
+ *         (('*' | '/') Value)*
+ *
+ * A definition starts with "Name <-" or "Name ->" at the beginning of a line and
+ * continues until the next definition, so rules can span multiple lines. Annotations
+ * like @lexical follow the arrow, just as in Parser::add_rule_str.
+ */
+impl<'input, T: 'input, I: Input + 'input> Parser<'input, T, I> {
+    pub fn from_grammar_str(grammar: &str) -> Result<Parser<'input, T, I>, GrammarError> {
+        let mut parser = Parser::new();
+        parser.load_grammar(grammar)?;
+        Ok(parser)
+    }
+
+    pub fn load_grammar(&mut self, grammar: &str) -> Result<(), GrammarError> {
+        let code = strip_comments(grammar);
+        let definition = Regex::new(r"(?m)^[ \t]*([A-Za-z_][A-Za-z0-9_]*)[ \t]*(<-|->)").unwrap();
+
+        let heads: Vec<_> = definition.captures_iter(&code).collect();
+        let first_start = heads
+            .first()
+            .map_or(code.len(), |c| c.get(0).unwrap().start());
+        if let Some(offset) = code[..first_start].find(|c: char| !c.is_whitespace()) {
+            return Err(GrammarError::new(
+                GrammarErrorKind::ExpectedRuleDefinition,
+                offset,
+            ));
+        }
+
+        for (i, head) in heads.iter().enumerate() {
+            let name = head.get(1).unwrap();
+            let right_start = head.get(0).unwrap().end();
+            let right_end = heads
+                .get(i + 1)
+                .map_or(code.len(), |c| c.get(0).unwrap().start());
+            self.try_add_rule_str(name.as_str(), &code[right_start..right_end], None)
+                .map_err(|mut err| {
+                    err.offset = match err.kind {
+                        GrammarErrorKind::DuplicateRule => name.start(),
+                        _ => right_start + err.offset,
+                    };
+                    err
+                })?;
+        }
+        Ok(())
+    }
+}
+
+// Replaces comments by spaces, so offsets into the result are offsets into the grammar
+fn strip_comments(grammar: &str) -> String {
+    let mut code = String::with_capacity(grammar.len());
+    let mut terminal_end = None;
+    let mut in_comment = false;
+    let mut escaped = false;
+    for c in grammar.chars() {
+        if in_comment {
+            if c == '\n' {
+                in_comment = false;
+                code.push(c);
+            } else {
+                code.push_str(&" ".repeat(c.len_utf8()));
+            }
+            continue;
+        }
+        match terminal_end {
+            Some(end) => {
+                if c == end && !escaped {
+                    terminal_end = None;
+                }
+                escaped = c == '\\' && !escaped;
+            }
+            None => match c {
+                '\'' | '"' => terminal_end = Some(c),
+                '[' => terminal_end = Some(']'),
+                '#' => {
+                    in_comment = true;
+                    code.push(' ');
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        code.push(c);
+    }
+    code
+}
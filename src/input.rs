@@ -0,0 +1,290 @@
+use crate::code_tokenizer::CodeTokenizer;
+use crate::error::GrammarErrorKind;
+use crate::parser::{ParsingExpression, TerminalParsingExpression};
+use crate::stream_tokenizer::StreamTokenizer;
+use crate::token_stream::TokenStream;
+use regex_automata::util::syntax;
+use regex_automata::{meta, Anchored, Input as Haystack};
+use std::fmt;
+use std::marker::PhantomData;
+
+/* What a parser reads, the I of Parser<T, I>: Text for text and Bytes for binary data (both
+ * CodeTokenizer), Stream for text read from a BufRead (StreamTokenizer), Tokens<K> for the tokens
+ * of an existing lexer (TokenStream). The combinators work with every input, only the terminals
+ * depend on it.
+ */
+pub trait Input: Sized {
+    type Tokenizer<'input>: Tokenizer<'input>
+    where
+        Self: 'input;
+
+    // The terminals of grammar strings, 'literal' and [regex]
+    fn literal<'input, T: 'input>(
+        literal: &str,
+    ) -> Result<Box<dyn ParsingExpression<T, Self> + 'input>, GrammarErrorKind>
+    where
+        Self: 'input,
+    {
+        Ok(TerminalParsingExpression::new(literal))
+    }
+    fn regex<'input, T: 'input>(
+        pattern: &str,
+    ) -> Result<Box<dyn ParsingExpression<T, Self> + 'input>, GrammarErrorKind>
+    where
+        Self: 'input,
+    {
+        TerminalParsingExpression::try_new_from_regex(pattern)
+    }
+}
+
+pub struct Text;
+
+impl Input for Text {
+    type Tokenizer<'input> = CodeTokenizer<'input>;
+}
+
+// The [regex] of grammar strings is a byte regex, text regexes never match bytes
+pub struct Bytes;
+
+impl Input for Bytes {
+    type Tokenizer<'input> = CodeTokenizer<'input>;
+
+    fn regex<'input, T: 'input>(
+        pattern: &str,
+    ) -> Result<Box<dyn ParsingExpression<T, Self> + 'input>, GrammarErrorKind> {
+        TerminalParsingExpression::try_new_from_bytes_regex(pattern)
+    }
+}
+
+pub struct Stream;
+
+impl Input for Stream {
+    type Tokenizer<'input> = StreamTokenizer<'input>;
+}
+
+// Tokens of kind K, matched by TokenParsingExpression. Grammar strings can't contain terminals
+pub struct Tokens<K>(PhantomData<K>);
+
+impl<K> Input for Tokens<K> {
+    type Tokenizer<'input>
+        = TokenStream<'input, K>
+    where
+        K: 'input;
+
+    fn literal<'input, T: 'input>(
+        _literal: &str,
+    ) -> Result<Box<dyn ParsingExpression<T, Self> + 'input>, GrammarErrorKind>
+    where
+        K: 'input,
+    {
+        Err(GrammarErrorKind::UnsupportedTerminal)
+    }
+
+    fn regex<'input, T: 'input>(
+        _pattern: &str,
+    ) -> Result<Box<dyn ParsingExpression<T, Self> + 'input>, GrammarErrorKind>
+    where
+        K: 'input,
+    {
+        Err(GrammarErrorKind::UnsupportedTerminal)
+    }
+}
+
+/* The regex of a [regex] terminal. Tokenizers only try it at their position (an anchored search),
+ * a regex that doesn't match there fails without searching the rest of the input.
+ */
+#[derive(Debug, Clone)]
+pub struct TerminalRegex {
+    pattern: String,
+    regex: meta::Regex,
+}
+
+impl TerminalRegex {
+    // Matches text, a match never ends inside of a character
+    pub fn new(pattern: &str) -> Result<TerminalRegex, GrammarErrorKind> {
+        Self::build(pattern, meta::Regex::new(pattern))
+    }
+
+    // Like regex::bytes::Regex, with (?-u) it matches bytes that aren't UTF-8
+    pub fn new_bytes(pattern: &str) -> Result<TerminalRegex, GrammarErrorKind> {
+        let regex = meta::Regex::builder()
+            .configure(meta::Config::new().utf8_empty(false))
+            .syntax(syntax::Config::new().utf8(false))
+            .build(pattern);
+        Self::build(pattern, regex)
+    }
+
+    fn build(
+        pattern: &str,
+        regex: Result<meta::Regex, meta::BuildError>,
+    ) -> Result<TerminalRegex, GrammarErrorKind> {
+        match regex {
+            Ok(regex) => Ok(TerminalRegex {
+                pattern: String::from(pattern),
+                regex,
+            }),
+            Err(err) => Err(GrammarErrorKind::InvalidRegex(err.to_string())),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // The end of the match that starts at start, the input before it is context for \b and ^
+    pub fn match_at(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        let input = Haystack::new(haystack)
+            .range(start..)
+            .anchored(Anchored::Yes);
+        self.regex.search(&input).map(|found| found.end())
+    }
+
+    // Where the next match from start on starts
+    pub fn find_from(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        let input = Haystack::new(haystack).range(start..);
+        self.regex.search(&input).map(|found| found.start())
+    }
+}
+
+impl fmt::Display for TerminalRegex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+/* The position in the input and the failures recorded so far, the role CodeTokenizer plays for
+ * text. Positions are offsets into the input, in bytes or tokens.
+ */
+pub trait Tokenizer<'input> {
+    fn get_state(&self) -> usize;
+    fn set_state(&mut self, state: usize);
+    // Whether everything was consumed
+    fn is_empty(&self) -> bool;
+    // What Skipper::Whitespace skips
+    fn skip_whitespaces(&mut self) {}
+    // Moves past the next character or token, false at the end of the input
+    fn skip_char(&mut self) -> bool;
+    // Terminals, inputs without text never match them
+    fn match_string(&mut self, _string: &str) -> bool {
+        false
+    }
+    fn match_regex(&mut self, _regex: &TerminalRegex) -> bool {
+        false
+    }
+    fn match_bytes(&mut self, _bytes: &[u8]) -> bool {
+        false
+    }
+    fn match_byte_range(&mut self, _low: u8, _high: u8) -> bool {
+        false
+    }
+    fn match_any_bytes(&mut self, _count: usize) -> bool {
+        false
+    }
+    fn match_bytes_regex(&mut self, _regex: &TerminalRegex) -> bool {
+        false
+    }
+    // Both start at 1, inputs without lines are on line 1 and the column is the position + 1
+    fn line_column(&self, position: usize) -> (usize, usize) {
+        (1, position + 1)
+    }
+    fn line_at(&self, _position: usize) -> &str {
+        ""
+    }
+    // Whether terminals skip whitespace on their own, the parser turns this off
+    fn set_skip_whitespace(&mut self, _enabled: bool) {}
+    // States pushed by expressions that are not popped again, see ParsingInformation::checkpoint
+    fn saved_states(&self) -> usize {
+        0
+    }
+    fn truncate_states(&mut self, _count: usize) {}
+    /* The parser will read the input from position on again, e.g. the next alternative of a
+     * choice. Inputs that are read on demand keep it until it is released.
+     */
+    fn hold(&mut self, _position: usize) {}
+    fn release(&mut self, _position: usize) {}
+
+    fn failures(&self) -> &Failures;
+    fn failures_mut(&mut self) -> &mut Failures;
+
+    fn record_failure(&mut self, position: usize, expected: &str) {
+        self.failures_mut().record(position, expected);
+    }
+    fn failure_mark(&self) -> (usize, usize) {
+        self.failures().mark()
+    }
+    fn summarize_failure(&mut self, mark: (usize, usize), position: usize, expected: &str) {
+        self.failures_mut().summarize(mark, position, expected);
+    }
+    fn save_failure(&self) -> (usize, Vec<String>) {
+        self.failures().save()
+    }
+    fn restore_failure(&mut self, saved: (usize, Vec<String>)) {
+        self.failures_mut().restore(saved);
+    }
+    fn merge_failure(&mut self, saved: (usize, Vec<String>)) {
+        self.failures_mut().merge(saved);
+    }
+    fn get_farthest_failure(&self) -> usize {
+        self.failures().farthest_failure
+    }
+    fn get_expected(&self) -> &[String] {
+        &self.failures().expected
+    }
+}
+
+// What was expected at the farthest position any terminal failed at
+#[derive(Debug, Default)]
+pub struct Failures {
+    farthest_failure: usize,
+    expected: Vec<String>,
+}
+
+impl Failures {
+    /* Remembers what was expected at the farthest position any terminal failed at,
+     * this is what gets reported if the whole parse fails.
+     */
+    pub fn record(&mut self, position: usize, expected: &str) {
+        if position > self.farthest_failure {
+            self.farthest_failure = position;
+            self.expected.clear();
+        }
+        if position == self.farthest_failure && !self.expected.iter().any(|e| e == expected) {
+            self.expected.push(String::from(expected));
+        }
+    }
+
+    pub fn mark(&self) -> (usize, usize) {
+        (self.farthest_failure, self.expected.len())
+    }
+
+    /* Replaces everything expected at position since the mark by a single description,
+     * e.g. the terminals of a rule that failed right at its start by the name of the rule.
+     */
+    pub fn summarize(&mut self, mark: (usize, usize), position: usize, expected: &str) {
+        if self.farthest_failure != position {
+            return;
+        }
+        if mark.0 == position {
+            self.expected.truncate(mark.1);
+        } else {
+            self.expected.clear();
+        }
+        self.record(position, expected);
+    }
+
+    pub fn save(&self) -> (usize, Vec<String>) {
+        (self.farthest_failure, self.expected.clone())
+    }
+
+    pub fn restore(&mut self, saved: (usize, Vec<String>)) {
+        self.farthest_failure = saved.0;
+        self.expected = saved.1;
+    }
+
+    // Adds failures saved elsewhere, as if they were recorded here
+    pub fn merge(&mut self, saved: (usize, Vec<String>)) {
+        for expected in &saved.1 {
+            self.record(saved.0, expected);
+        }
+    }
+}
@@ -1,3 +1,13 @@
+pub mod analysis;
 pub mod code_tokenizer;
+pub mod error;
 pub mod expression_tokenizer;
+pub mod find;
+pub mod grammar;
+pub mod input;
 pub mod parser;
+pub mod span;
+pub mod stream_tokenizer;
+pub mod token_stream;
+pub mod trace;
+pub mod tree;
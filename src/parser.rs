@@ -1,29 +1,165 @@
+#![allow(clippy::new_ret_no_self)]
 use crate::code_tokenizer::CodeTokenizer;
+use crate::error::{GrammarError, GrammarErrorKind, ParseError, ParseErrorKind};
 use crate::expression_tokenizer::{ExpressionToken, ExpressionTokenizer};
-use regex::Regex;
-use std::collections::HashMap;
+use crate::input::{Bytes, Input, Stream, TerminalRegex, Text, Tokenizer, Tokens};
+use crate::span::Span;
+use crate::stream_tokenizer::StreamTokenizer;
+use crate::token_stream::TokenStream;
+use crate::trace::Tracer;
+use crate::tree::CSTNode;
+use std::cell::{OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::io::BufRead;
 use std::marker::PhantomData;
 use std::ops;
+use std::rc::Rc;
 
-pub struct ASTNode {}
 pub struct ParsingResult<T> {
+    // Everything the result consumed, including skipped whitespace around it
     pub parsed_string_start: usize,
     pub parsed_string_end: usize,
+    // Only the matched tokens, from the start of the first to the end of the last one
+    pub token_start: usize,
+    pub token_end: usize,
     pub sub_results: Vec<ParsingResult<T>>,
     pub selected_choice: Option<usize>,
     pub rule_result: Option<T>,
+    // Set if the result was matched by a labeled expression like lhs:Product
+    pub label: Option<String>,
+    // The syntax tree of a rule, only built by Parser::parse_tree. Shared with the packrat cache
+    pub node: Option<Rc<CSTNode>>,
 }
 
 impl<T> ParsingResult<T> {
+    /* A result that consumed the input from start to end, all of it tokens. Custom expressions
+     * can set the other fields with struct update syntax:
+     *     ParsingResult { sub_results, ..ParsingResult::new(start, end) }
+     */
+    pub fn new(start: usize, end: usize) -> Self {
+        ParsingResult {
+            parsed_string_start: start,
+            parsed_string_end: end,
+            token_start: start,
+            token_end: end,
+            sub_results: Vec::new(),
+            selected_choice: None,
+            rule_result: None,
+            label: None,
+            node: None,
+        }
+    }
+
+    // A result that consumed nothing, e.g. of a predicate
+    pub fn empty(position: usize) -> Self {
+        Self::new(position, position)
+    }
+
+    // Narrows the tokens to a part of the consumed input, without the skipped whitespace
+    pub fn with_tokens(mut self, token_start: usize, token_end: usize) -> Self {
+        self.token_start = token_start;
+        self.token_end = token_end;
+        self
+    }
+
+    // The exact span of the matched tokens, without the skipped whitespace around them
+    pub fn span(&self) -> Span {
+        Span::new(self.token_start, self.token_end)
+    }
+
+    pub fn full_span(&self) -> Span {
+        Span::new(self.parsed_string_start, self.parsed_string_end)
+    }
+
+    pub fn leading_trivia(&self) -> Span {
+        Span::new(self.parsed_string_start, self.token_start)
+    }
+
+    pub fn trailing_trivia(&self) -> Span {
+        Span::new(self.token_end, self.parsed_string_end)
+    }
+
+    // Borrowed from the input, so callbacks can return it without copying
+    pub fn text<'input>(&self, tokenizer: &CodeTokenizer<'input>) -> &'input str {
+        tokenizer.get_substr(self.token_start, self.token_end)
+    }
+
+    // Like text, for byte input
+    pub fn bytes<'input>(&self, tokenizer: &CodeTokenizer<'input>) -> &'input [u8] {
+        tokenizer.get_bytes(self.token_start, self.token_end)
+    }
+
+    // Like text, borrowed from the buffer of a stream. None if the start was already discarded
+    pub fn streamed_text<'t>(&self, tokenizer: &'t StreamTokenizer) -> Option<&'t str> {
+        tokenizer.get_substr(self.token_start, self.token_end)
+    }
+
+    // Like text, for the tokens of a lexer
+    pub fn tokens<'input, K>(&self, tokenizer: &TokenStream<'input, K>) -> &'input [K] {
+        tokenizer.get_tokens(self.token_start, self.token_end)
+    }
+
+    // Spans the tokens of the sub results, a result without tokens is empty at its start
+    fn enclose_sub_tokens(&mut self) {
+        let mut tokens = self
+            .sub_results
+            .iter()
+            .filter(|res| res.token_start < res.token_end);
+        match tokens.next() {
+            Some(first) => {
+                self.token_start = first.token_start;
+                self.token_end = tokens.last().unwrap_or(first).token_end;
+            }
+            None => {
+                self.token_start = self.parsed_string_start;
+                self.token_end = self.parsed_string_start;
+            }
+        }
+    }
+
+    // The first result labeled with the name, results of other rules are not searched
+    pub fn get(&self, label: &str) -> Option<&ParsingResult<T>> {
+        self.all(label).into_iter().next()
+    }
+
+    // All results labeled with the name, e.g. every rhs in lhs:Product (op:'+' rhs:Product)*
+    pub fn all(&self, label: &str) -> Vec<&ParsingResult<T>> {
+        let mut found = Vec::new();
+        self.collect_labeled(label, &mut found);
+        found
+    }
+
+    // The nodes of the outermost rules matched inside of this result
+    fn collect_nodes(self, to: &mut Vec<Rc<CSTNode>>) {
+        match self.node {
+            Some(node) => to.push(node),
+            None => {
+                for sub_result in self.sub_results {
+                    sub_result.collect_nodes(to);
+                }
+            }
+        }
+    }
+
+    fn collect_labeled<'r>(&'r self, label: &str, found: &mut Vec<&'r ParsingResult<T>>) {
+        if self.label.as_deref() == Some(label) {
+            found.push(self);
+            return;
+        }
+        for sub_result in &self.sub_results {
+            sub_result.collect_labeled(label, found);
+        }
+    }
+
     pub fn flatten(self) -> Vec<ParsingResult<T>> {
         let mut v = Vec::new();
         self.flatten_rec(&mut v);
-        return v;
+        v
     }
 
     fn flatten_rec(mut self, to: &mut Vec<ParsingResult<T>>) {
-        if self.sub_results.len() == 0 {
+        if self.sub_results.is_empty() {
             return;
         }
         let sub_results_cpy = self.sub_results;
@@ -41,54 +177,508 @@ impl<T> ops::Index<usize> for ParsingResult<T> {
     type Output = ParsingResult<T>;
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self.sub_results.get(index).unwrap()
+        self.sub_results.get(index).unwrap()
+    }
+}
+
+const RULE_ANNOTATIONS: [&str; 5] = ["lexical", "drop", "inline", "flatten", "recover"];
+
+// Results can borrow from the input, so a parser only parses inputs that live as long as it
+pub type RuleCallback<'input, T, I = Text> =
+    Box<dyn Fn(ParsingResult<T>, &<I as Input>::Tokenizer<'input>) -> T + 'input>;
+type ResultCloner<T> = fn(&T) -> T;
+
+// A parsed rule string: the expression and the annotations in front of it
+struct RuleDefinition<'input, T, I: Input> {
+    expression: Box<dyn ParsingExpression<T, I> + 'input>,
+    annotations: Vec<String>,
+    // The synchronization expression of @recover(...)
+    recover: Option<Box<dyn ParsingExpression<T, I> + 'input>>,
+}
+
+pub struct Rule<'input, T, I: Input + 'input = Text> {
+    pub(crate) expression: Box<dyn ParsingExpression<T, I> + 'input>,
+    callback: Option<RuleCallback<'input, T, I>>,
+    // Lexical rules match their terminals without skipping anything in between
+    lexical: bool,
+    // How the rule shows up in the AST, see Parser::parse_ast
+    pub(crate) drop: bool,
+    pub(crate) inline: bool,
+    pub(crate) flatten: bool,
+    // Where to continue after the rule failed, see Parser::parse_tree_recovering
+    pub(crate) recover: Option<Box<dyn ParsingExpression<T, I> + 'input>>,
+}
+
+impl<'input, T, I: Input> Rule<'input, T, I> {
+    fn annotations(&self) -> Vec<&'static str> {
+        let flags = [self.lexical, self.drop, self.inline, self.flatten];
+        RULE_ANNOTATIONS
+            .iter()
+            .zip(flags.iter())
+            .filter(|(_, set)| **set)
+            .map(|(annotation, _)| *annotation)
+            .collect()
     }
 }
 
-type RuleCallback<T> = Box<dyn Fn(ParsingResult<T>, &CodeTokenizer) -> T>;
-pub struct Rule<T> {
-    expression: Box<dyn ParsingExpression<T>>,
-    callback: Option<RuleCallback<T>>,
+// What is skipped before and after every terminal
+pub enum Skipper {
+    None,
+    // Unicode whitespace, the default
+    Whitespace,
+    // Whatever the rule matches, e.g. Spacing <- ([ \t\r\n] | '//' [^\n]*)*
+    Rule(String),
 }
 
-pub struct ParsingInformation<'a, T> {
-    rules: &'a HashMap<String, Rule<T>>,
-    tokenizer: &'a mut CodeTokenizer,
+/* Packrat cache: (rule name, start position, whether whitespace is skipped) -> result of the
+ * rule, None if the rule failed.
+ * Only the summary of a rule is stored (the sub results are consumed by the callback anyway),
+ * the callback result is handed out as a clone on every hit.
+ */
+struct MemoTable<'a, T> {
+    entries: HashMap<(&'a str, usize, bool), Option<ParsingResult<T>>>,
+    cloner: ResultCloner<T>,
+}
+
+impl<'a, T> MemoTable<'a, T> {
+    fn new(cloner: ResultCloner<T>) -> Self {
+        MemoTable {
+            entries: HashMap::new(),
+            cloner,
+        }
+    }
+
+    fn get(&self, key: (&'a str, usize, bool)) -> Option<Option<ParsingResult<T>>> {
+        self.entries.get(&key).map(|entry| {
+            entry
+                .as_ref()
+                .map(|res| clone_rule_result(res, self.cloner))
+        })
+    }
+
+    fn insert(&mut self, key: (&'a str, usize, bool), result: &Option<ParsingResult<T>>) {
+        let entry = result
+            .as_ref()
+            .map(|res| clone_rule_result(res, self.cloner));
+        self.entries.insert(key, entry);
+    }
+}
+
+fn clone_rule_result<T>(res: &ParsingResult<T>, cloner: ResultCloner<T>) -> ParsingResult<T> {
+    ParsingResult {
+        selected_choice: res.selected_choice,
+        rule_result: res.rule_result.as_ref().map(cloner),
+        node: res.node.clone(),
+        ..ParsingResult::new(res.parsed_string_start, res.parsed_string_end)
+            .with_tokens(res.token_start, res.token_end)
+    }
+}
+
+/* One entry per rule that is currently being matched. If a rule is entered again at the same
+ * position before it returned, the rule is left recursive and the frame becomes the head of a
+ * seed growing loop: the recursive call gets the seed (the last successful result) and every
+ * frame above the head depends on that seed, so it must not be memoized.
+ */
+struct RuleFrame<'a, T> {
+    rule: &'a str,
+    position: usize,
+    seed: Option<ParsingResult<T>>,
+    left_recursive: bool,
+    involved: bool,
+}
+
+pub struct ParsingInformation<'a, 'input, T, I: Input + 'input = Text> {
+    rules: &'a HashMap<String, Rule<'input, T, I>>,
+    // Rules whose input isn't kept for their callbacks
+    unbounded_rules: &'a HashSet<String>,
+    tokenizer: &'a mut I::Tokenizer<'input>,
+    skipper: &'a Skipper,
+    // False inside of lexical rules and the skipper rule
+    skipping: bool,
+    memo: Option<MemoTable<'a, T>>,
+    left_recursion: Option<ResultCloner<T>>,
+    rule_stack: Vec<RuleFrame<'a, T>>,
+    // Build a CSTNode for every rule instead of calling the callbacks
+    tree: bool,
+    // Whether rules annotated with @recover(...) recover from failures, only in tree mode
+    recovering: bool,
+    // Set when a sequence failed after a cut, nothing may backtrack until a rule recovers
+    hard_failure: bool,
+    // An error that stops the parse and its position, e.g. an undefined rule
+    fatal_error: Option<(ParseErrorKind, usize)>,
+    tracer: Option<Rc<RefCell<dyn Tracer>>>,
+}
+
+/* Saves the position of the tokenizer and goes back to it when dropped, unless it was
+ * committed. It derefs to the ParsingInformation, so children are matched through it:
+ *     let mut checkpoint = info.checkpoint();
+ *     let child_result = checkpoint.match_expression(child)?;
+ *     let end = checkpoint.commit();
+ */
+pub struct Checkpoint<'c, 'a, 'input, T, I: Input + 'input = Text> {
+    info: &'c mut ParsingInformation<'a, 'input, T, I>,
+    start: usize,
+    committed: bool,
+}
+
+impl<'c, 'a, 'input, T, I: Input> Checkpoint<'c, 'a, 'input, T, I> {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    // Keeps the current position and returns it
+    pub fn commit(mut self) -> usize {
+        self.committed = true;
+        self.info.tokenizer.get_state()
+    }
+}
+
+impl<'c, 'a, 'input, T, I: Input> Drop for Checkpoint<'c, 'a, 'input, T, I> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.info.tokenizer.set_state(self.start);
+        }
+    }
+}
+
+impl<'c, 'a, 'input, T, I: Input> ops::Deref for Checkpoint<'c, 'a, 'input, T, I> {
+    type Target = ParsingInformation<'a, 'input, T, I>;
+
+    fn deref(&self) -> &Self::Target {
+        self.info
+    }
+}
+
+impl<'c, 'a, 'input, T, I: Input> ops::DerefMut for Checkpoint<'c, 'a, 'input, T, I> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.info
+    }
+}
+
+impl<'a, 'input, T, I: Input> ParsingInformation<'a, 'input, T, I> {
+    pub fn checkpoint(&mut self) -> Checkpoint<'_, 'a, 'input, T, I> {
+        Checkpoint {
+            start: self.tokenizer.get_state(),
+            info: self,
+            committed: false,
+        }
+    }
+
+    pub fn tokenizer(&mut self) -> &mut I::Tokenizer<'input> {
+        self.tokenizer
+    }
+
+    // Every expression matches its children through this, so the tracer sees all of them
+    pub fn match_expression(
+        &mut self,
+        expression: &dyn ParsingExpression<T, I>,
+    ) -> Option<ParsingResult<T>> {
+        let tracer = match self.tracer {
+            Some(ref tracer) => Rc::clone(tracer),
+            None => return self.match_guarded(expression),
+        };
+        let rule = self.rule_stack.last().map_or("", |frame| frame.rule);
+        let dump = expression.dump();
+        let offset = self.tokenizer.get_state();
+        tracer.borrow_mut().enter(rule, &dump, offset);
+        let result = self.match_guarded(expression);
+        match result {
+            Some(ref res) => tracer.borrow_mut().success(rule, &dump, res.span()),
+            None => tracer.borrow_mut().failure(rule, &dump, offset),
+        }
+        tracer
+            .borrow_mut()
+            .exit(rule, &dump, self.tokenizer.get_state());
+        result
+    }
+
+    /* Like match_expression, for callers that go on from the current position if the expression
+     * fails, e.g. the end of a repetition. The input from there on is held until it returns.
+     */
+    pub fn match_or_backtrack(
+        &mut self,
+        expression: &dyn ParsingExpression<T, I>,
+    ) -> Option<ParsingResult<T>> {
+        let start = self.tokenizer.get_state();
+        self.tokenizer.hold(start);
+        let result = self.match_expression(expression);
+        self.tokenizer.release(start);
+        result
+    }
+
+    // A failed expression consumes nothing, and states it left on the stack are dropped
+    fn match_guarded(
+        &mut self,
+        expression: &dyn ParsingExpression<T, I>,
+    ) -> Option<ParsingResult<T>> {
+        let start = self.tokenizer.get_state();
+        let saved_states = self.tokenizer.saved_states();
+        let result = expression.matches(self);
+        self.tokenizer.truncate_states(saved_states);
+        if result.is_none() {
+            self.tokenizer.set_state(start);
+        }
+        result
+    }
+
+    pub fn skip(&mut self) {
+        if !self.skipping {
+            return;
+        }
+        match self.skipper {
+            Skipper::None => {}
+            Skipper::Whitespace => self.tokenizer.skip_whitespaces(),
+            Skipper::Rule(name) => {
+                let rule = match self.rules.get(name) {
+                    Some(rule) => rule,
+                    None => {
+                        let position = self.tokenizer.get_state();
+                        return self.fail(ParseErrorKind::UndefinedRule(name.clone()), position);
+                    }
+                };
+                // Whatever the skipper expected is not what the user is interested in
+                let failure = self.tokenizer.save_failure();
+                let tracer = self.tracer.take();
+                self.skipping = false;
+                rule.expression.matches(self);
+                self.skipping = true;
+                self.tracer = tracer;
+                self.tokenizer.restore_failure(failure);
+            }
+        }
+    }
+
+    // Stops the parse, nothing may backtrack and try something else. Parser::run reports it.
+    fn fail(&mut self, kind: ParseErrorKind, position: usize) {
+        if self.fatal_error.is_none() {
+            self.fatal_error = Some((kind, position));
+        }
+        self.hard_failure = true;
+    }
+
+    // The rule a repetition that doesn't consume anything is in
+    fn endless_loop(&mut self) {
+        let rule = self.rule_stack.last().map_or("", |frame| frame.rule);
+        let position = self.tokenizer.get_state();
+        self.fail(ParseErrorKind::EndlessLoop(String::from(rule)), position);
+    }
+
+    // Where the next terminal would start, after skipping
+    fn skipped_position(&mut self) -> usize {
+        let mut checkpoint = self.checkpoint();
+        checkpoint.skip();
+        checkpoint.tokenizer.get_state()
+    }
+
+    fn find_frame(&self, rule: &str, position: usize) -> Option<usize> {
+        // Positions on the stack never decrease, so only the topmost frames can match
+        for (index, frame) in self.rule_stack.iter().enumerate().rev() {
+            if frame.position < position {
+                break;
+            }
+            if frame.position == position && frame.rule == rule {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn read_seed(&mut self, index: usize) -> Option<ParsingResult<T>> {
+        let cloner = match self.left_recursion {
+            Some(cloner) => cloner,
+            None => {
+                let frame = &self.rule_stack[index];
+                let kind = ParseErrorKind::LeftRecursion(String::from(frame.rule));
+                self.fail(kind, frame.position);
+                return None;
+            }
+        };
+        if !self.rule_stack[index].left_recursive {
+            // grow_seed parses the rule from its start again, the rule releases it
+            self.tokenizer.hold(self.rule_stack[index].position);
+        }
+        self.rule_stack[index].left_recursive = true;
+        for frame in &mut self.rule_stack[index + 1..] {
+            frame.involved = true;
+        }
+        let seed = self.rule_stack[index]
+            .seed
+            .as_ref()
+            .map(|res| clone_rule_result(res, cloner));
+        if let Some(ref res) = seed {
+            self.tokenizer.set_state(res.parsed_string_end);
+        }
+        seed
+    }
 }
 
-pub trait ParsingExpression<T> {
+pub trait ParsingExpression<T, I: Input = Text> {
     fn dump(&self) -> String {
-        return String::from("ParsingExpression");
+        String::from("ParsingExpression")
+    }
+    // The expressions this expression is made of, used to analyse a grammar before parsing
+    fn children(&self) -> Vec<&dyn ParsingExpression<T, I>> {
+        Vec::new()
+    }
+    // Name of the rule that is matched if this is a non-terminal
+    fn referenced_rule(&self) -> Option<&str> {
+        None
+    }
+    // What is matched if this is a terminal
+    fn terminal(&self) -> Option<&TerminalType> {
+        None
+    }
+    // Whether the expression can succeed without consuming input, given the nullable rules
+    fn is_nullable(&self, _nullable_rules: &HashSet<&str>) -> bool {
+        false
+    }
+    // The children that can be matched at the position this expression starts at
+    fn leftmost_children(
+        &self,
+        _nullable_rules: &HashSet<&str>,
+    ) -> Vec<&dyn ParsingExpression<T, I>> {
+        self.children()
+    }
+    fn is_repetition(&self) -> bool {
+        false
+    }
+    fn is_cut(&self) -> bool {
+        false
+    }
+    // Whether the dump has to be put in parentheses to apply a quantifier, predicate or label to it
+    fn is_sequence(&self) -> bool {
+        false
+    }
+    fn matches(&self, tokenizer: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>>;
+}
+
+// The dump of the expression a quantifier, predicate or label applies to
+fn dump_operand<T, I: Input>(expression: &dyn ParsingExpression<T, I>) -> String {
+    if expression.is_sequence() {
+        format!("({})", expression.dump())
+    } else {
+        expression.dump()
     }
-    fn matches(&self, tokenizer: &mut ParsingInformation<T>) -> Option<ParsingResult<T>>;
 }
 
 pub enum TerminalType {
     SIMPLE(String),
-    REGEX(Regex),
+    REGEX(TerminalRegex),
+    // The terminals below are meant for byte input, see Parser::parse_bytes
+    BYTES(Vec<u8>),
+    RANGE(u8, u8),
+    BYTESREGEX(TerminalRegex),
+    INTEGER(IntegerFormat),
+}
+
+// Fixed width unsigned integers, little or big endian
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegerFormat {
+    U8,
+    U16Le,
+    U16Be,
+    U32Le,
+    U32Be,
+}
+
+impl IntegerFormat {
+    pub fn width(self) -> usize {
+        match self {
+            IntegerFormat::U8 => 1,
+            IntegerFormat::U16Le | IntegerFormat::U16Be => 2,
+            IntegerFormat::U32Le | IntegerFormat::U32Be => 4,
+        }
+    }
+
+    // Reads the integer from the matched bytes, e.g. IntegerFormat::U16Be.decode(r.bytes(t))
+    pub fn decode(self, bytes: &[u8]) -> u32 {
+        let bytes = &bytes[..self.width()];
+        match self {
+            IntegerFormat::U8 | IntegerFormat::U16Be | IntegerFormat::U32Be => bytes
+                .iter()
+                .fold(0, |value, byte| value << 8 | u32::from(*byte)),
+            IntegerFormat::U16Le | IntegerFormat::U32Le => bytes
+                .iter()
+                .rev()
+                .fold(0, |value, byte| value << 8 | u32::from(*byte)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            IntegerFormat::U8 => "u8",
+            IntegerFormat::U16Le => "u16le",
+            IntegerFormat::U16Be => "u16be",
+            IntegerFormat::U32Le => "u32le",
+            IntegerFormat::U32Be => "u32be",
+        }
+    }
 }
 
-pub struct TerminalParsingExpression<T> {
+pub struct TerminalParsingExpression<T, I = Text> {
     content: TerminalType,
-    _marker: PhantomData<T>,
+    _marker: PhantomData<(T, I)>,
 }
 
-impl<T: 'static> TerminalParsingExpression<T> {
-    pub fn new(p_name: &str) -> Box<dyn ParsingExpression<T>> {
+impl<'input, T: 'input, I: Input + 'input> TerminalParsingExpression<T, I> {
+    pub fn new(p_name: &str) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(TerminalParsingExpression {
             content: TerminalType::SIMPLE(String::from(p_name)),
             _marker: Default::default(),
         })
     }
-    pub fn new_from_regex(p_name: &str) -> Box<dyn ParsingExpression<T>> {
+    pub fn new_from_regex(p_name: &str) -> Box<dyn ParsingExpression<T, I> + 'input> {
+        Self::try_new_from_regex(p_name).expect("Invalid regex")
+    }
+    pub fn try_new_from_regex(
+        p_name: &str,
+    ) -> Result<Box<dyn ParsingExpression<T, I> + 'input>, GrammarErrorKind> {
+        Ok(Box::new(TerminalParsingExpression {
+            content: TerminalType::REGEX(TerminalRegex::new(p_name)?),
+            _marker: Default::default(),
+        }))
+    }
+    pub fn new_bytes(bytes: &[u8]) -> Box<dyn ParsingExpression<T, I> + 'input> {
+        Self::new_from_type(TerminalType::BYTES(bytes.to_vec()))
+    }
+    // One byte from low to high, both inclusive
+    pub fn new_byte_range(low: u8, high: u8) -> Box<dyn ParsingExpression<T, I> + 'input> {
+        Self::new_from_type(TerminalType::RANGE(low, high))
+    }
+    pub fn new_from_bytes_regex(p_name: &str) -> Box<dyn ParsingExpression<T, I> + 'input> {
+        Self::try_new_from_bytes_regex(p_name).expect("Invalid regex")
+    }
+    pub fn try_new_from_bytes_regex(
+        p_name: &str,
+    ) -> Result<Box<dyn ParsingExpression<T, I> + 'input>, GrammarErrorKind> {
+        Ok(Self::new_from_type(TerminalType::BYTESREGEX(
+            TerminalRegex::new_bytes(p_name)?,
+        )))
+    }
+    pub fn new_integer(format: IntegerFormat) -> Box<dyn ParsingExpression<T, I> + 'input> {
+        Self::new_from_type(TerminalType::INTEGER(format))
+    }
+    fn new_from_type(content: TerminalType) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(TerminalParsingExpression {
-            content: TerminalType::REGEX(Regex::new(p_name).unwrap()),
+            content,
             _marker: Default::default(),
         })
     }
 }
 
-impl<T> ParsingExpression<T> for TerminalParsingExpression<T> {
+impl<T, I: Input> ParsingExpression<T, I> for TerminalParsingExpression<T, I> {
+    fn terminal(&self) -> Option<&TerminalType> {
+        Some(&self.content)
+    }
+    fn is_nullable(&self, _nullable_rules: &HashSet<&str>) -> bool {
+        match &self.content {
+            TerminalType::SIMPLE(str) => str.is_empty(),
+            TerminalType::REGEX(reg) => reg.match_at(b"", 0).is_some(),
+            TerminalType::BYTES(bytes) => bytes.is_empty(),
+            TerminalType::RANGE(..) | TerminalType::INTEGER(_) => false,
+            TerminalType::BYTESREGEX(reg) => reg.match_at(b"", 0).is_some(),
+        }
+    }
     fn dump(&self) -> String {
         match &self.content {
             TerminalType::SIMPLE(str) => {
@@ -98,37 +688,92 @@ impl<T> ParsingExpression<T> for TerminalParsingExpression<T> {
                 ret
             }
             TerminalType::REGEX(reg) => reg.to_string(),
+            TerminalType::BYTES(bytes) => format!("b'{}'", bytes.escape_ascii()),
+            TerminalType::RANGE(low, high) => {
+                format!("b[{}-{}]", [*low].escape_ascii(), [*high].escape_ascii())
+            }
+            TerminalType::BYTESREGEX(reg) => format!("b{}", reg),
+            TerminalType::INTEGER(format) => format.name().to_string(),
         }
     }
 
-    fn matches(&self, info: &mut ParsingInformation<T>) -> Option<ParsingResult<T>> {
-        let start = info.tokenizer.push_state();
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        let mut checkpoint = info.checkpoint();
+        let start = checkpoint.start();
+        checkpoint.skip();
+        let token_start = checkpoint.tokenizer.get_state();
         let does_match = match &self.content {
-            TerminalType::SIMPLE(str) => info.tokenizer.match_string(str.as_ref()),
-            TerminalType::REGEX(reg) => info.tokenizer.match_regex(reg),
+            TerminalType::SIMPLE(str) => checkpoint.tokenizer.match_string(str.as_ref()),
+            TerminalType::REGEX(reg) => checkpoint.tokenizer.match_regex(reg),
+            TerminalType::BYTES(bytes) => checkpoint.tokenizer.match_bytes(bytes),
+            TerminalType::RANGE(low, high) => checkpoint.tokenizer.match_byte_range(*low, *high),
+            TerminalType::BYTESREGEX(reg) => checkpoint.tokenizer.match_bytes_regex(reg),
+            TerminalType::INTEGER(format) => checkpoint.tokenizer.match_any_bytes(format.width()),
         };
         if does_match {
-            Some(ParsingResult {
-                parsed_string_start: start,
-                parsed_string_end: info.tokenizer.update_state(),
-                sub_results: Vec::new(),
-                selected_choice: None,
-                rule_result: None,
-            })
+            let token_end = checkpoint.tokenizer.get_state();
+            checkpoint.skip();
+            Some(ParsingResult::new(start, checkpoint.commit()).with_tokens(token_start, token_end))
         } else {
-            info.tokenizer.pop_state();
+            let position = checkpoint.tokenizer.get_state();
+            checkpoint.tokenizer.record_failure(position, &self.dump());
             None
         }
     }
 }
 
-pub struct NonTerminalParsingExpression<T> {
+// A single token of an existing lexer, matched by a predicate on its kind
+pub struct TokenParsingExpression<'input, T, K> {
     name: String,
+    predicate: Box<dyn Fn(&K) -> bool + 'input>,
     _marker: PhantomData<T>,
 }
 
-impl<T: 'static> NonTerminalParsingExpression<T> {
-    pub fn new(p_name: &str) -> Box<dyn ParsingExpression<T>> {
+impl<'input, T: 'input, K: 'input> TokenParsingExpression<'input, T, K> {
+    // The name is what errors report as expected
+    pub fn new(
+        p_name: &str,
+        predicate: impl Fn(&K) -> bool + 'input,
+    ) -> Box<dyn ParsingExpression<T, Tokens<K>> + 'input> {
+        Box::new(TokenParsingExpression {
+            name: String::from(p_name),
+            predicate: Box::new(predicate),
+            _marker: Default::default(),
+        })
+    }
+}
+
+impl<'input, T, K> ParsingExpression<T, Tokens<K>> for TokenParsingExpression<'input, T, K> {
+    fn is_nullable(&self, _nullable_rules: &HashSet<&str>) -> bool {
+        false
+    }
+    fn dump(&self) -> String {
+        self.name.clone()
+    }
+
+    fn matches(&self, info: &mut ParsingInformation<T, Tokens<K>>) -> Option<ParsingResult<T>> {
+        let mut checkpoint = info.checkpoint();
+        let start = checkpoint.start();
+        checkpoint.skip();
+        let token_start = checkpoint.tokenizer.get_state();
+        if checkpoint.tokenizer.match_token(&*self.predicate) {
+            let token_end = checkpoint.tokenizer.get_state();
+            checkpoint.skip();
+            Some(ParsingResult::new(start, checkpoint.commit()).with_tokens(token_start, token_end))
+        } else {
+            checkpoint.tokenizer.record_failure(token_start, &self.name);
+            None
+        }
+    }
+}
+
+pub struct NonTerminalParsingExpression<T, I = Text> {
+    name: String,
+    _marker: PhantomData<(T, I)>,
+}
+
+impl<'input, T: 'input, I: Input + 'input> NonTerminalParsingExpression<T, I> {
+    pub fn new(p_name: &str) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(NonTerminalParsingExpression {
             name: String::from(p_name),
             _marker: Default::default(),
@@ -136,41 +781,232 @@ impl<T: 'static> NonTerminalParsingExpression<T> {
     }
 }
 
-impl<T> ParsingExpression<T> for NonTerminalParsingExpression<T> {
+impl<T, I: Input> ParsingExpression<T, I> for NonTerminalParsingExpression<T, I> {
+    fn is_nullable(&self, nullable_rules: &HashSet<&str>) -> bool {
+        nullable_rules.contains(self.name.as_str())
+    }
+    fn referenced_rule(&self) -> Option<&str> {
+        Some(&self.name)
+    }
     fn dump(&self) -> String {
-        return String::from(format!("{}", self.name));
-    }
-    fn matches(&self, mut info: &mut ParsingInformation<T>) -> Option<ParsingResult<T>> {
-        let rule = info
-            .rules
-            .get(&self.name)
-            .expect("No rule for this non-terminal!");
-        match rule.expression.matches(&mut info) {
-            Some(res) => {
-                let mut res_cpy = ParsingResult {
-                    parsed_string_start: res.parsed_string_start,
-                    parsed_string_end: res.parsed_string_end,
-                    sub_results: vec![],
-                    selected_choice: res.selected_choice,
-                    rule_result: None,
-                };
-                if let Some(ref callback) = rule.callback {
-                    res_cpy.rule_result = Some(callback(res, &info.tokenizer));
+        self.name.to_string()
+    }
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        let start = info.tokenizer.get_state();
+        let (name, rule) = match info.rules.get_key_value(&self.name) {
+            Some(entry) => entry,
+            None => {
+                info.fail(ParseErrorKind::UndefinedRule(self.name.clone()), start);
+                return None;
+            }
+        };
+        if let Some(index) = info.find_frame(name, start) {
+            return info.read_seed(index);
+        }
+        let memo_key = (name.as_str(), start, info.skipping);
+        if let Some(ref memo) = info.memo {
+            if let Some(entry) = memo.get(memo_key) {
+                match entry {
+                    Some(ref res) => info.tokenizer.set_state(res.parsed_string_end),
+                    None => {
+                        let position = info.skipped_position();
+                        info.tokenizer.record_failure(position, name);
+                    }
+                }
+                return entry;
+            }
+        }
+
+        info.rule_stack.push(RuleFrame {
+            rule: name,
+            position: start,
+            seed: None,
+            left_recursive: false,
+            involved: false,
+        });
+        // Failures of a recoverable rule are tracked on their own, to see how far it got
+        let recover = rule.recover.as_ref().filter(|_| info.recovering);
+        let outer_failure = recover.map(|_| info.tokenizer.save_failure());
+        if recover.is_some() {
+            info.tokenizer.restore_failure((0, Vec::new()));
+        }
+        let failure_mark = info.tokenizer.failure_mark();
+        let mut result = self.match_rule(rule, info);
+        if result.is_none() {
+            // A rule that can't even start is reported by its name instead of its terminals
+            let position = info.skipped_position();
+            info.tokenizer
+                .summarize_failure(failure_mark, position, name);
+        }
+        if info.rule_stack.last().unwrap().left_recursive {
+            result = self.grow_seed(rule, info, start, result);
+            info.tokenizer.release(start);
+        }
+        if let (Some(sync), Some(outer_failure)) = (recover, outer_failure) {
+            result = self.recover(sync.as_ref(), info, start, result, outer_failure);
+        }
+        let frame = info.rule_stack.pop().unwrap();
+
+        if !frame.involved {
+            if let Some(ref mut memo) = info.memo {
+                memo.insert(memo_key, &result);
+            }
+        }
+        result
+    }
+}
+
+impl<T, I: Input> NonTerminalParsingExpression<T, I> {
+    /* If the rule failed after matching some of the input, the input from the failure up to
+     * the end of the synchronization expression is skipped and becomes an error node.
+     * A rule that fails right at its start just fails, e.g. at the end of Statement*.
+     */
+    fn recover(
+        &self,
+        sync: &dyn ParsingExpression<T, I>,
+        info: &mut ParsingInformation<T, I>,
+        start: usize,
+        result: Option<ParsingResult<T>>,
+        outer_failure: (usize, Vec<String>),
+    ) -> Option<ParsingResult<T>> {
+        let failure = info.tokenizer.save_failure();
+        let (position, expected) = failure.clone();
+        let token_start = info.skipped_position();
+        if result.is_none() && position > token_start && info.fatal_error.is_none() {
+            // Even a failure after a cut only goes up to here
+            let hard_failure = info.hard_failure;
+            info.hard_failure = false;
+            let error = ParseError::new(
+                ParseErrorKind::NoMatch,
+                position,
+                expected.clone(),
+                info.tokenizer,
+            );
+            info.tokenizer.set_state(position);
+            loop {
+                if let Some(sync_result) = info.match_expression(sync) {
+                    // The failure is reported by the error node, not by the parse
+                    info.tokenizer.restore_failure(outer_failure);
+                    let token_end = sync_result.token_end;
+                    return Some(ParsingResult {
+                        node: Some(Rc::new(CSTNode {
+                            rule: self.name.clone(),
+                            span: Span::new(token_start, token_end),
+                            children: Vec::new(),
+                            error: Some(error),
+                        })),
+                        ..ParsingResult::new(start, info.tokenizer.get_state())
+                            .with_tokens(token_start, token_end)
+                    });
+                }
+                if !info.tokenizer.skip_char() {
+                    info.tokenizer.set_state(start);
+                    info.hard_failure = hard_failure;
+                    break;
+                }
+            }
+        }
+        info.tokenizer.restore_failure(outer_failure);
+        info.tokenizer.merge_failure(failure);
+        result
+    }
+
+    // Re-parses a left recursive rule as long as the result keeps getting longer
+    fn grow_seed<'input>(
+        &self,
+        rule: &Rule<'input, T, I>,
+        info: &mut ParsingInformation<'_, 'input, T, I>,
+        start: usize,
+        mut result: Option<ParsingResult<T>>,
+    ) -> Option<ParsingResult<T>> {
+        let cloner = info.left_recursion.unwrap();
+        while let Some(ref res) = result {
+            let end = res.parsed_string_end;
+            info.rule_stack.last_mut().unwrap().seed = Some(clone_rule_result(res, cloner));
+            info.tokenizer.set_state(start);
+            match self.match_rule(rule, info) {
+                Some(next) if next.parsed_string_end > end => result = Some(next),
+                None if info.hard_failure => return None,
+                _ => {
+                    info.tokenizer.set_state(end);
+                    break;
                 }
-                Some(res_cpy)
             }
-            None => None,
         }
+        result
+    }
+
+    fn match_rule<'input>(
+        &self,
+        rule: &Rule<'input, T, I>,
+        info: &mut ParsingInformation<'_, 'input, T, I>,
+    ) -> Option<ParsingResult<T>> {
+        if rule.lexical && info.skipping {
+            // Skip around the rule as a whole, but not between its terminals
+            let mut checkpoint = info.checkpoint();
+            checkpoint.skip();
+            checkpoint.skipping = false;
+            let result = self.match_callback(rule, &mut checkpoint);
+            checkpoint.skipping = true;
+            let mut res = result?;
+            checkpoint.skip();
+            res.parsed_string_start = checkpoint.start();
+            res.parsed_string_end = checkpoint.commit();
+            return Some(res);
+        }
+        self.match_callback(rule, info)
+    }
+
+    fn match_callback<'input>(
+        &self,
+        rule: &Rule<'input, T, I>,
+        info: &mut ParsingInformation<'_, 'input, T, I>,
+    ) -> Option<ParsingResult<T>> {
+        // The callback can read the text of the rule, unless it could be the whole input
+        let start = info.tokenizer.get_state();
+        let hold = rule.callback.is_some()
+            && !info.tree
+            && !info.unbounded_rules.contains(self.name.as_str());
+        if hold {
+            info.tokenizer.hold(start);
+        }
+        let result = info.match_expression(rule.expression.as_ref()).map(|res| {
+            let mut res_cpy = ParsingResult {
+                selected_choice: res.selected_choice,
+                ..ParsingResult::new(res.parsed_string_start, res.parsed_string_end)
+                    .with_tokens(res.token_start, res.token_end)
+            };
+            if info.tree {
+                let span = res.span();
+                let mut children = Vec::new();
+                res.collect_nodes(&mut children);
+                res_cpy.node = Some(Rc::new(CSTNode {
+                    rule: self.name.clone(),
+                    span,
+                    children,
+                    error: None,
+                }));
+            } else if let Some(ref callback) = rule.callback {
+                res_cpy.rule_result = Some(callback(res, info.tokenizer));
+            }
+            res_cpy
+        });
+        if hold {
+            info.tokenizer.release(start);
+        }
+        result
     }
 }
 
-pub struct SequenceParsingExpression<T: 'static> {
-    children: Vec<Box<dyn ParsingExpression<T>>>,
+pub struct SequenceParsingExpression<'input, T, I: Input = Text> {
+    children: Vec<Box<dyn ParsingExpression<T, I> + 'input>>,
     _marker: PhantomData<T>,
 }
 
-impl<T: 'static> SequenceParsingExpression<T> {
-    pub fn new(p_children: Vec<Box<dyn ParsingExpression<T>>>) -> Box<dyn ParsingExpression<T>> {
+impl<'input, T: 'input, I: Input + 'input> SequenceParsingExpression<'input, T, I> {
+    pub fn new(
+        p_children: Vec<Box<dyn ParsingExpression<T, I> + 'input>>,
+    ) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(SequenceParsingExpression {
             children: p_children,
             _marker: Default::default(),
@@ -178,48 +1014,81 @@ impl<T: 'static> SequenceParsingExpression<T> {
     }
 }
 
-impl<T> ParsingExpression<T> for SequenceParsingExpression<T> {
+impl<'input, T, I: Input> ParsingExpression<T, I> for SequenceParsingExpression<'input, T, I> {
+    fn is_nullable(&self, nullable_rules: &HashSet<&str>) -> bool {
+        self.children
+            .iter()
+            .all(|child| child.is_nullable(nullable_rules))
+    }
+    fn leftmost_children(
+        &self,
+        nullable_rules: &HashSet<&str>,
+    ) -> Vec<&dyn ParsingExpression<T, I>> {
+        let mut leftmost = Vec::new();
+        for child in &self.children {
+            leftmost.push(child.as_ref());
+            if !child.is_nullable(nullable_rules) {
+                break;
+            }
+        }
+        leftmost
+    }
+    fn children(&self) -> Vec<&dyn ParsingExpression<T, I>> {
+        self.children.iter().map(|child| child.as_ref()).collect()
+    }
     fn dump(&self) -> String {
         let mut ret = String::new();
-        let mut i = 0;
-        for child in &self.children {
+        for (i, child) in self.children.iter().enumerate() {
             ret.push_str(&child.dump());
             if i < self.children.len() - 1 {
-                ret.push_str(" ");
+                ret.push(' ');
             }
-            i += 1;
         }
-        return ret;
+        ret
     }
-    fn matches(&self, info: &mut ParsingInformation<T>) -> Option<ParsingResult<T>> {
-        let start = info.tokenizer.push_state();
-        let mut result = ParsingResult {
-            parsed_string_start: start,
-            parsed_string_end: 0,
-            sub_results: Vec::new(),
-            selected_choice: None,
-            rule_result: None,
-        };
+    fn is_sequence(&self) -> bool {
+        self.children.len() > 1
+    }
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        let mut checkpoint = info.checkpoint();
+        let mut result = ParsingResult::empty(checkpoint.start());
+        // Failures after a cut are tracked on their own, they are reported if the sequence fails
+        let mut outer_failure = None;
         for child in &self.children {
-            let child_result = child.matches(info);
-            if child_result.is_none() {
-                info.tokenizer.pop_state();
-                return None;
+            if child.is_cut() && outer_failure.is_none() {
+                outer_failure = Some(checkpoint.tokenizer.save_failure());
+                checkpoint.tokenizer.restore_failure((0, Vec::new()));
+            }
+            match checkpoint.match_expression(child.as_ref()) {
+                Some(child_result) => result.sub_results.push(child_result),
+                None => {
+                    if outer_failure.is_some() {
+                        checkpoint.hard_failure = true;
+                    }
+                    return None;
+                }
             }
-            result.sub_results.push(child_result.unwrap());
         }
-        result.parsed_string_end = info.tokenizer.update_state();
-        return Some(result);
+        if let Some(outer_failure) = outer_failure {
+            let failure = checkpoint.tokenizer.save_failure();
+            checkpoint.tokenizer.restore_failure(outer_failure);
+            checkpoint.tokenizer.merge_failure(failure);
+        }
+        result.parsed_string_end = checkpoint.commit();
+        result.enclose_sub_tokens();
+        Some(result)
     }
 }
 
-pub struct ChoiceParsingExpression<T: 'static> {
-    children: Vec<Box<dyn ParsingExpression<T>>>,
+pub struct ChoiceParsingExpression<'input, T, I: Input = Text> {
+    children: Vec<Box<dyn ParsingExpression<T, I> + 'input>>,
     _marker: PhantomData<T>,
 }
 
-impl<T: 'static> ChoiceParsingExpression<T> {
-    pub fn new(p_children: Vec<Box<dyn ParsingExpression<T>>>) -> Box<dyn ParsingExpression<T>> {
+impl<'input, T: 'input, I: Input + 'input> ChoiceParsingExpression<'input, T, I> {
+    pub fn new(
+        p_children: Vec<Box<dyn ParsingExpression<T, I> + 'input>>,
+    ) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(ChoiceParsingExpression {
             children: p_children,
             _marker: Default::default(),
@@ -227,391 +1096,823 @@ impl<T: 'static> ChoiceParsingExpression<T> {
     }
 }
 
-impl<T> ParsingExpression<T> for ChoiceParsingExpression<T> {
+impl<'input, T, I: Input> ParsingExpression<T, I> for ChoiceParsingExpression<'input, T, I> {
+    fn is_nullable(&self, nullable_rules: &HashSet<&str>) -> bool {
+        self.children
+            .iter()
+            .any(|child| child.is_nullable(nullable_rules))
+    }
+    fn children(&self) -> Vec<&dyn ParsingExpression<T, I>> {
+        self.children.iter().map(|child| child.as_ref()).collect()
+    }
     fn dump(&self) -> String {
         let mut ret = String::from("(");
-        let mut i = 0;
-        for child in &self.children {
+        for (i, child) in self.children.iter().enumerate() {
             ret.push_str(&child.dump());
             if i < self.children.len() - 1 {
                 ret.push_str(" | ");
             }
-            i += 1;
         }
-        ret.push_str(")");
-        return ret;
+        ret.push(')');
+        ret
     }
-    fn matches(&self, mut info: &mut ParsingInformation<T>) -> Option<ParsingResult<T>> {
-        let mut i = 0usize;
-        for child in &self.children {
-            let start = info.tokenizer.push_state();
-
-            match child.matches(&mut info) {
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        for (i, child) in self.children.iter().enumerate() {
+            let mut checkpoint = info.checkpoint();
+            // Only the alternatives after this one need its start again
+            let child_result = if i + 1 < self.children.len() {
+                checkpoint.match_or_backtrack(child.as_ref())
+            } else {
+                checkpoint.match_expression(child.as_ref())
+            };
+            match child_result {
                 Some(child_res) => {
+                    let (token_start, token_end) = (child_res.token_start, child_res.token_end);
                     return Some(ParsingResult {
-                        parsed_string_start: start,
-                        parsed_string_end: info.tokenizer.update_state(),
                         sub_results: vec![child_res],
                         selected_choice: Some(i),
-                        rule_result: None,
-                    })
+                        ..ParsingResult::new(checkpoint.start(), checkpoint.commit())
+                            .with_tokens(token_start, token_end)
+                    });
+                }
+                None => {
+                    if checkpoint.hard_failure {
+                        return None;
+                    }
                 }
-                None => info.tokenizer.pop_state(),
             }
-            i += 1;
         }
-        return None;
+        None
     }
 }
 
-pub struct OneOrMoreParsingExpression<T: 'static> {
-    child: Box<dyn ParsingExpression<T>>,
+pub struct OneOrMoreParsingExpression<'input, T, I: Input = Text> {
+    child: Box<dyn ParsingExpression<T, I> + 'input>,
 }
 
-impl<T: 'static> OneOrMoreParsingExpression<T> {
-    pub fn new(child: Box<dyn ParsingExpression<T>>) -> Box<dyn ParsingExpression<T>> {
+impl<'input, T: 'input, I: Input + 'input> OneOrMoreParsingExpression<'input, T, I> {
+    pub fn new(
+        child: Box<dyn ParsingExpression<T, I> + 'input>,
+    ) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(OneOrMoreParsingExpression { child })
     }
 }
-impl<T> ParsingExpression<T> for OneOrMoreParsingExpression<T> {
+impl<'input, T, I: Input> ParsingExpression<T, I> for OneOrMoreParsingExpression<'input, T, I> {
+    fn is_nullable(&self, nullable_rules: &HashSet<&str>) -> bool {
+        self.child.is_nullable(nullable_rules)
+    }
+    fn is_repetition(&self) -> bool {
+        true
+    }
+    fn children(&self) -> Vec<&dyn ParsingExpression<T, I>> {
+        vec![self.child.as_ref()]
+    }
     fn dump(&self) -> String {
-        let mut ret = self.child.dump();
+        let mut ret = dump_operand(self.child.as_ref());
         ret.push('+');
-        return ret;
+        ret
     }
-    fn matches(&self, mut info: &mut ParsingInformation<T>) -> Option<ParsingResult<T>> {
-        let mut res = ParsingResult {
-            parsed_string_start: info.tokenizer.get_state(),
-            parsed_string_end: 0,
-            sub_results: Vec::new(),
-            selected_choice: None,
-            rule_result: None,
-        };
-        match self.child.matches(&mut info) {
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        let mut res = ParsingResult::empty(info.tokenizer.get_state());
+        match info.match_expression(self.child.as_ref()) {
             Some(child_res) => res.sub_results.push(child_res),
             None => return None,
         }
         let start_state = info.tokenizer.get_state();
         loop {
             if info.tokenizer.is_empty() {
-                return Some(res);
+                break;
             }
-            match self.child.matches(&mut info) {
+            match info.match_or_backtrack(self.child.as_ref()) {
                 Some(child_res) => {
                     if info.tokenizer.get_state() == start_state {
-                        panic!("No characters are being consumed in a OneOrMoreParsingExpression, this is an endless loop!");
+                        info.endless_loop();
+                        return None;
                     }
                     res.sub_results.push(child_res)
-                },
+                }
+                None if info.hard_failure => return None,
                 None => break,
             }
         }
         res.parsed_string_end = info.tokenizer.get_state();
+        res.enclose_sub_tokens();
         Some(res)
     }
 }
 
-pub struct ZeroOrMoreParsingExpression<T: 'static> {
-    child: Box<dyn ParsingExpression<T>>,
+pub struct ZeroOrMoreParsingExpression<'input, T, I: Input = Text> {
+    child: Box<dyn ParsingExpression<T, I> + 'input>,
 }
 
-impl<T: 'static> ZeroOrMoreParsingExpression<T> {
-    pub fn new(child: Box<dyn ParsingExpression<T>>) -> Box<dyn ParsingExpression<T>> {
+impl<'input, T: 'input, I: Input + 'input> ZeroOrMoreParsingExpression<'input, T, I> {
+    pub fn new(
+        child: Box<dyn ParsingExpression<T, I> + 'input>,
+    ) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(ZeroOrMoreParsingExpression { child })
     }
 }
-impl<T> ParsingExpression<T> for ZeroOrMoreParsingExpression<T> {
+impl<'input, T, I: Input> ParsingExpression<T, I> for ZeroOrMoreParsingExpression<'input, T, I> {
+    fn is_nullable(&self, _nullable_rules: &HashSet<&str>) -> bool {
+        true
+    }
+    fn is_repetition(&self) -> bool {
+        true
+    }
+    fn children(&self) -> Vec<&dyn ParsingExpression<T, I>> {
+        vec![self.child.as_ref()]
+    }
     fn dump(&self) -> String {
-        let mut ret = self.child.dump();
+        let mut ret = dump_operand(self.child.as_ref());
         ret.push('*');
-        return ret;
+        ret
     }
-    fn matches(&self, mut info: &mut ParsingInformation<T>) -> Option<ParsingResult<T>> {
-        let mut res = ParsingResult {
-            parsed_string_start: info.tokenizer.get_state(),
-            parsed_string_end: 0,
-            sub_results: Vec::new(),
-            selected_choice: None,
-            rule_result: None,
-        };
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        let mut res = ParsingResult::empty(info.tokenizer.get_state());
         loop {
             if info.tokenizer.is_empty() {
-                return Some(res);
+                break;
             }
             let start_state = info.tokenizer.get_state();
-            match self.child.matches(&mut info) {
+            match info.match_or_backtrack(self.child.as_ref()) {
                 Some(child_res) => {
                     if info.tokenizer.get_state() == start_state {
-                        panic!("No characters are being consumed in a ZeroOrMoreParsingExpression, this is an endless loop!");
+                        info.endless_loop();
+                        return None;
                     }
                     res.sub_results.push(child_res)
-                },
+                }
+                None if info.hard_failure => return None,
                 None => break,
             }
         }
         res.parsed_string_end = info.tokenizer.get_state();
+        res.enclose_sub_tokens();
         Some(res)
     }
 }
 
-pub struct OptionalParsingExpression<T> {
-    child: Box<dyn ParsingExpression<T>>,
+pub struct OptionalParsingExpression<'input, T, I: Input = Text> {
+    child: Box<dyn ParsingExpression<T, I> + 'input>,
 }
 
-impl<T: 'static> OptionalParsingExpression<T> {
-    pub fn new(child: Box<dyn ParsingExpression<T>>) -> Box<dyn ParsingExpression<T>> {
+impl<'input, T: 'input, I: Input + 'input> OptionalParsingExpression<'input, T, I> {
+    pub fn new(
+        child: Box<dyn ParsingExpression<T, I> + 'input>,
+    ) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(OptionalParsingExpression { child })
     }
 }
-impl<T> ParsingExpression<T> for OptionalParsingExpression<T> {
+impl<'input, T, I: Input> ParsingExpression<T, I> for OptionalParsingExpression<'input, T, I> {
+    fn is_nullable(&self, _nullable_rules: &HashSet<&str>) -> bool {
+        true
+    }
+    fn children(&self) -> Vec<&dyn ParsingExpression<T, I>> {
+        vec![self.child.as_ref()]
+    }
     fn dump(&self) -> String {
-        let mut ret = self.child.dump();
+        let mut ret = dump_operand(self.child.as_ref());
         ret.push('?');
-        return ret;
+        ret
     }
-    fn matches(&self, mut info: &mut ParsingInformation<T>) -> Option<ParsingResult<T>> {
-        match self.child.matches(&mut info) {
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        match info.match_or_backtrack(self.child.as_ref()) {
             Some(res) => Some(res),
-            None => Some(ParsingResult {
-                parsed_string_start: info.tokenizer.get_state(),
-                parsed_string_end: info.tokenizer.get_state(),
-                sub_results: Vec::new(),
-                selected_choice: None,
-                rule_result: None,
-            }),
+            None if info.hard_failure => None,
+            None => Some(ParsingResult::empty(info.tokenizer.get_state())),
         }
     }
 }
 
-pub struct AndPredicateParsingExpression<T> {
-    child: Box<dyn ParsingExpression<T>>,
+pub struct AndPredicateParsingExpression<'input, T, I: Input = Text> {
+    child: Box<dyn ParsingExpression<T, I> + 'input>,
 }
 
-impl<T: 'static> AndPredicateParsingExpression<T> {
-    pub fn new(child: Box<dyn ParsingExpression<T>>) -> Box<dyn ParsingExpression<T>> {
+impl<'input, T: 'input, I: Input + 'input> AndPredicateParsingExpression<'input, T, I> {
+    pub fn new(
+        child: Box<dyn ParsingExpression<T, I> + 'input>,
+    ) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(AndPredicateParsingExpression { child })
     }
 }
-impl<T> ParsingExpression<T> for AndPredicateParsingExpression<T> {
+impl<'input, T, I: Input> ParsingExpression<T, I> for AndPredicateParsingExpression<'input, T, I> {
+    fn is_nullable(&self, _nullable_rules: &HashSet<&str>) -> bool {
+        true
+    }
+    fn children(&self) -> Vec<&dyn ParsingExpression<T, I>> {
+        vec![self.child.as_ref()]
+    }
     fn dump(&self) -> String {
         let mut ret = String::from('?');
-        ret.push_str(self.child.dump().as_str());
-        return ret;
+        ret.push_str(dump_operand(self.child.as_ref()).as_str());
+        ret
     }
-    fn matches(&self, mut info: &mut ParsingInformation<T>) -> Option<ParsingResult<T>> {
-        info.tokenizer.push_state();
-        match self.child.matches(&mut info) {
-            Some(res) => {
-                info.tokenizer.pop_state();
-                Some(res)
-            }
-            None => {
-                info.tokenizer.pop_state();
-                None
-            }
-        }
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        // The checkpoint is never committed, a predicate consumes nothing
+        let mut checkpoint = info.checkpoint();
+        let start = checkpoint.start();
+        checkpoint.match_or_backtrack(self.child.as_ref())?;
+        // Like the not predicate, the result is empty and nothing belongs into the tree
+        Some(ParsingResult::empty(start))
     }
 }
 
-pub struct NotPredicateParsingExpression<T> {
-    child: Box<dyn ParsingExpression<T>>,
+pub struct NotPredicateParsingExpression<'input, T, I: Input = Text> {
+    child: Box<dyn ParsingExpression<T, I> + 'input>,
 }
 
-impl<T: 'static> NotPredicateParsingExpression<T> {
-    pub fn new(child: Box<dyn ParsingExpression<T>>) -> Box<dyn ParsingExpression<T>> {
+impl<'input, T: 'input, I: Input + 'input> NotPredicateParsingExpression<'input, T, I> {
+    pub fn new(
+        child: Box<dyn ParsingExpression<T, I> + 'input>,
+    ) -> Box<dyn ParsingExpression<T, I> + 'input> {
         Box::new(NotPredicateParsingExpression { child })
     }
 }
-impl<T> ParsingExpression<T> for NotPredicateParsingExpression<T> {
+impl<'input, T, I: Input> ParsingExpression<T, I> for NotPredicateParsingExpression<'input, T, I> {
+    fn is_nullable(&self, _nullable_rules: &HashSet<&str>) -> bool {
+        true
+    }
+    fn children(&self) -> Vec<&dyn ParsingExpression<T, I>> {
+        vec![self.child.as_ref()]
+    }
     fn dump(&self) -> String {
         let mut ret = String::from('!');
-        ret.push_str(self.child.dump().as_str());
-        return ret;
+        ret.push_str(dump_operand(self.child.as_ref()).as_str());
+        ret
     }
-    fn matches(&self, mut info: &mut ParsingInformation<T>) -> Option<ParsingResult<T>> {
-        info.tokenizer.push_state();
-        match self.child.matches(&mut info) {
-            Some(_res) => {
-                info.tokenizer.pop_state();
-                None
-            }
-            None => {
-                info.tokenizer.pop_state();
-                Some(ParsingResult {
-                    parsed_string_start: 0,
-                    parsed_string_end: 0,
-                    sub_results: vec![],
-                    selected_choice: None,
-                    rule_result: None,
-                })
-            }
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        let mut checkpoint = info.checkpoint();
+        // Failures inside of the predicate are what it expects, they are no parse errors
+        let failure = checkpoint.tokenizer.save_failure();
+        let child_result = checkpoint.match_or_backtrack(self.child.as_ref());
+        if checkpoint.hard_failure {
+            return None;
         }
+        checkpoint.tokenizer.restore_failure(failure);
+        let start = checkpoint.start();
+        match child_result {
+            Some(_res) => None,
+            None => Some(ParsingResult::empty(start)),
+        }
+    }
+}
+
+// Commits the sequence it is in: once the cut is passed, a failure can't be backtracked
+pub struct CutParsingExpression<T, I = Text> {
+    _marker: PhantomData<(T, I)>,
+}
+
+impl<'input, T: 'input, I: Input + 'input> CutParsingExpression<T, I> {
+    pub fn new() -> Box<dyn ParsingExpression<T, I> + 'input> {
+        Box::new(CutParsingExpression {
+            _marker: Default::default(),
+        })
+    }
+}
+impl<T, I: Input> ParsingExpression<T, I> for CutParsingExpression<T, I> {
+    fn is_nullable(&self, _nullable_rules: &HashSet<&str>) -> bool {
+        true
+    }
+    fn is_cut(&self) -> bool {
+        true
+    }
+    fn dump(&self) -> String {
+        String::from('~')
+    }
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        let position = info.tokenizer.get_state();
+        Some(ParsingResult::empty(position))
+    }
+}
+
+pub struct LabeledParsingExpression<'input, T, I: Input = Text> {
+    label: String,
+    child: Box<dyn ParsingExpression<T, I> + 'input>,
+}
+
+impl<'input, T: 'input, I: Input + 'input> LabeledParsingExpression<'input, T, I> {
+    pub fn new(
+        label: &str,
+        child: Box<dyn ParsingExpression<T, I> + 'input>,
+    ) -> Box<dyn ParsingExpression<T, I> + 'input> {
+        Box::new(LabeledParsingExpression {
+            label: String::from(label),
+            child,
+        })
+    }
+}
+impl<'input, T, I: Input> ParsingExpression<T, I> for LabeledParsingExpression<'input, T, I> {
+    fn is_nullable(&self, nullable_rules: &HashSet<&str>) -> bool {
+        self.child.is_nullable(nullable_rules)
+    }
+    fn children(&self) -> Vec<&dyn ParsingExpression<T, I>> {
+        vec![self.child.as_ref()]
+    }
+    fn dump(&self) -> String {
+        format!("{}:{}", self.label, dump_operand(self.child.as_ref()))
+    }
+    fn matches(&self, info: &mut ParsingInformation<T, I>) -> Option<ParsingResult<T>> {
+        let mut result = info.match_expression(self.child.as_ref())?;
+        result.label = Some(self.label.clone());
+        Some(result)
     }
 }
 
-pub struct Parser<T> {
-    rules: HashMap<String, Rule<T>>,
+// What a parse produces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ParseMode {
+    // The result of the callback of the start rule
+    Callbacks,
+    Tree,
+    // A tree with error nodes for the rules that recovered
+    Recovering,
 }
 
-impl<T: 'static> Parser<T> {
-    pub fn new() -> Parser<T> {
+pub struct Parser<'input, T, I: Input + 'input = Text> {
+    pub(crate) rules: HashMap<String, Rule<'input, T, I>>,
+    // Definitions rejected by try_add_rule because the rule already existed
+    pub(crate) duplicate_rules: Vec<String>,
+    pub(crate) skipper: Skipper,
+    cloner: Option<ResultCloner<T>>,
+    packrat: bool,
+    pub(crate) left_recursion: bool,
+    tracer: Option<Rc<RefCell<dyn Tracer>>>,
+    // A rule with a repetition that can match nothing, found before the first parse
+    endless_rule: OnceCell<Option<String>>,
+    // See analysis::unbounded_rules, found before the first parse
+    unbounded_rules: OnceCell<HashSet<String>>,
+}
+
+impl<'input, T: 'input, I: Input + 'input> Default for Parser<'input, T, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'input, T: 'input, I: Input + 'input> Parser<'input, T, I> {
+    pub fn new() -> Parser<'input, T, I> {
         Parser {
             rules: HashMap::new(),
+            duplicate_rules: Vec::new(),
+            skipper: Skipper::Whitespace,
+            cloner: None,
+            packrat: false,
+            left_recursion: false,
+            tracer: None,
+            endless_rule: OnceCell::new(),
+            unbounded_rules: OnceCell::new(),
         }
     }
     pub fn add_rule(
         &mut self,
         left_side: &str,
-        right_side: Box<dyn ParsingExpression<T>>,
-        callback: Option<RuleCallback<T>>,
+        right_side: Box<dyn ParsingExpression<T, I> + 'input>,
+        callback: Option<RuleCallback<'input, T, I>>,
     ) {
-        assert!(!self.rules.contains_key(left_side));
+        if let Err(err) = self.try_add_rule(left_side, right_side, callback) {
+            panic!("Invalid PEG grammar: {}", err);
+        }
+    }
+
+    pub fn try_add_rule(
+        &mut self,
+        left_side: &str,
+        right_side: Box<dyn ParsingExpression<T, I> + 'input>,
+        callback: Option<RuleCallback<'input, T, I>>,
+    ) -> Result<(), GrammarError> {
+        if self.rules.contains_key(left_side) {
+            self.duplicate_rules.push(String::from(left_side));
+            return Err(GrammarError::new(GrammarErrorKind::DuplicateRule, 0).in_rule(left_side));
+        }
+        self.endless_rule = OnceCell::new();
+        self.unbounded_rules = OnceCell::new();
         self.rules.insert(
             String::from(left_side),
             Rule {
                 expression: right_side,
                 callback,
+                lexical: false,
+                drop: false,
+                inline: false,
+                flatten: false,
+                recover: None,
             },
         );
+        Ok(())
     }
-    pub fn validate(&self, start_non_terminal: &str, code: &str) -> bool {
-        let mut tokenizer = CodeTokenizer::new(code);
-        let rule_result = NonTerminalParsingExpression::new(start_non_terminal).matches(
-            &mut ParsingInformation {
-                rules: &self.rules,
-                tokenizer: &mut tokenizer,
-            },
-        );
-        assert!(tokenizer.only_one_state_left());
-        rule_result.is_some() && tokenizer.is_empty()
+    pub fn set_callback(
+        &mut self,
+        left_side: &str,
+        callback: Option<RuleCallback<'input, T, I>>,
+    ) -> Result<(), GrammarError> {
+        match self.rules.get_mut(left_side) {
+            Some(rule) => {
+                rule.callback = callback;
+                Ok(())
+            }
+            None => Err(GrammarError::new(GrammarErrorKind::UndefinedRule, 0).in_rule(left_side)),
+        }
     }
 
-    pub fn parse(&self, start_non_terminal: &str, code: &str) -> Result<T, &'static str> {
-        let mut tokenizer = CodeTokenizer::new(code);
-        let rule_result = NonTerminalParsingExpression::new(start_non_terminal).matches(
-            &mut ParsingInformation {
-                rules: &self.rules,
-                tokenizer: &mut tokenizer,
-            },
-        );
-        assert!(tokenizer.only_one_state_left());
+    // See trace::TracePrinter for a tracer that renders the whole parse attempt
+    pub fn set_tracer(&mut self, tracer: Option<Rc<RefCell<dyn Tracer>>>) {
+        self.tracer = tracer;
+    }
+
+    pub fn set_skipper(&mut self, skipper: Skipper) {
+        self.skipper = skipper;
+    }
+
+    pub fn set_lexical(&mut self, left_side: &str, lexical: bool) -> Result<(), GrammarError> {
+        match self.rules.get_mut(left_side) {
+            Some(rule) => {
+                rule.lexical = lexical;
+                Ok(())
+            }
+            None => Err(GrammarError::new(GrammarErrorKind::UndefinedRule, 0).in_rule(left_side)),
+        }
+    }
 
+    // Parses from the current state of the tokenizer on, returns the result and where it ends
+    fn parse_from(
+        &self,
+        start_non_terminal: &str,
+        tokenizer: &mut I::Tokenizer<'input>,
+    ) -> Result<(T, usize), ParseError> {
+        let offset = tokenizer.get_state();
+        match self.run(start_non_terminal, tokenizer, ParseMode::Callbacks)? {
+            None => Err(Self::error_at_farthest_failure(
+                ParseErrorKind::NoMatch,
+                offset,
+                tokenizer,
+            )),
+            Some(parsing_result) => {
+                let end = parsing_result.parsed_string_end;
+                let result = Self::callback_result(start_non_terminal, parsing_result, tokenizer)?;
+                Ok((result, end))
+            }
+        }
+    }
+
+    fn callback_result(
+        start_non_terminal: &str,
+        parsing_result: ParsingResult<T>,
+        tokenizer: &I::Tokenizer<'input>,
+    ) -> Result<T, ParseError> {
+        match parsing_result.rule_result {
+            Some(rule_result) => Ok(rule_result),
+            None => Err(ParseError::new(
+                ParseErrorKind::MissingCallback(String::from(start_non_terminal)),
+                0,
+                Vec::new(),
+                tokenizer,
+            )),
+        }
+    }
+
+    // The result of the start rule, if it matched the whole input
+    pub(crate) fn complete_result(
+        rule_result: Option<ParsingResult<T>>,
+        tokenizer: &I::Tokenizer<'input>,
+    ) -> Result<ParsingResult<T>, ParseError> {
         match rule_result {
-            None => Err("There is no result!"),
-            Some(parsing_result) => match parsing_result.rule_result {
-                Some(rule_result) => {
-                    if !tokenizer.is_empty() {
-                        Err("There are tokens that haven't been parsed!")
-                    } else {
-                        Ok(rule_result)
-                    }
-                }
-                None => Err("There is no callback registered for the rule!"),
-            },
+            None => Err(Self::error_at_farthest_failure(
+                ParseErrorKind::NoMatch,
+                0,
+                tokenizer,
+            )),
+            Some(parsing_result) if !tokenizer.is_empty() => Err(Self::error_at_farthest_failure(
+                ParseErrorKind::UnparsedInput,
+                parsing_result.parsed_string_end,
+                tokenizer,
+            )),
+            Some(parsing_result) => Ok(parsing_result),
+        }
+    }
+
+    pub(crate) fn error_at_farthest_failure(
+        kind: ParseErrorKind,
+        position: usize,
+        tokenizer: &I::Tokenizer<'input>,
+    ) -> ParseError {
+        let farthest = tokenizer.get_farthest_failure();
+        if farthest >= position {
+            ParseError::new(kind, farthest, tokenizer.get_expected().to_vec(), tokenizer)
+        } else {
+            ParseError::new(kind, position, Vec::new(), tokenizer)
+        }
+    }
+
+    pub(crate) fn run(
+        &self,
+        start_non_terminal: &str,
+        tokenizer: &mut I::Tokenizer<'input>,
+        mode: ParseMode,
+    ) -> Result<Option<ParsingResult<T>>, ParseError> {
+        // Such a grammar is rejected before any input is parsed
+        let endless_rule = self.endless_rule.get_or_init(|| {
+            let mut repetitions = self.nullable_repetitions().into_iter();
+            repetitions.next().map(|(rule, _)| rule)
+        });
+        if let Some(rule) = endless_rule {
+            let kind = ParseErrorKind::EndlessLoop(rule.clone());
+            let position = tokenizer.get_state();
+            return Err(ParseError::new(kind, position, Vec::new(), tokenizer));
+        }
+        // Skipping is done by the terminals, according to the skipper of the parser
+        tokenizer.set_skip_whitespace(false);
+        let start = NonTerminalParsingExpression::new(start_non_terminal);
+        let mut info = ParsingInformation {
+            rules: &self.rules,
+            unbounded_rules: self.unbounded_rules.get_or_init(|| self.unbounded_rules()),
+            tokenizer,
+            skipper: &self.skipper,
+            skipping: true,
+            memo: self.cloner.filter(|_| self.packrat).map(MemoTable::new),
+            left_recursion: self.cloner.filter(|_| self.left_recursion),
+            rule_stack: Vec::new(),
+            tree: mode != ParseMode::Callbacks,
+            recovering: mode == ParseMode::Recovering,
+            hard_failure: false,
+            fatal_error: None,
+            tracer: self.tracer.clone(),
+        };
+        let rule_result = info.match_expression(start.as_ref());
+        if let Some((kind, position)) = info.fatal_error {
+            return Err(ParseError::new(kind, position, Vec::new(), tokenizer));
         }
+        Ok(rule_result)
     }
 
+    /* Panics if the rule string is invalid, see try_add_rule_str for the error. A rule that
+     * refers to itself before consuming input, like "Sum '+' Product", can only be parsed after
+     * set_left_recursion, which needs T: Clone.
+     */
     pub fn add_rule_str(
         &mut self,
         left_side: &str,
         right_side: &str,
-        callback: Option<RuleCallback<T>>,
+        callback: Option<RuleCallback<'input, T, I>>,
     ) {
-        self.add_rule(
-            left_side,
-            Self::parse_rule(&mut ExpressionTokenizer::new(right_side)),
-            callback,
-        );
+        if let Err(err) = self.try_add_rule_str(left_side, right_side, callback) {
+            panic!("Invalid PEG grammar: {}", err);
+        }
     }
 
-    fn parse_rule(tokenizer: &mut ExpressionTokenizer) -> Box<dyn ParsingExpression<T>> {
+    pub fn try_add_rule_str(
+        &mut self,
+        left_side: &str,
+        right_side: &str,
+        callback: Option<RuleCallback<'input, T, I>>,
+    ) -> Result<(), GrammarError> {
+        let definition = Self::parse_rule_str(right_side).map_err(|err| err.in_rule(left_side))?;
+        self.try_add_rule(left_side, definition.expression, callback)?;
+        let rule = self.rules.get_mut(left_side).unwrap();
+        for annotation in definition.annotations {
+            match annotation.as_str() {
+                "lexical" => rule.lexical = true,
+                "drop" => rule.drop = true,
+                "inline" => rule.inline = true,
+                "flatten" => rule.flatten = true,
+                _ => {}
+            }
+        }
+        rule.recover = definition.recover;
+        Ok(())
+    }
+
+    /* A rule string may start with annotations that modify the whole rule:
+     * "@lexical [a-z] [a-z0-9]*" matches without skipping whitespace between the terminals,
+     * "@recover(';') Name '=' Value ';'" skips to the next ';' if the rule fails.
+     */
+    fn parse_rule_str(right_side: &str) -> Result<RuleDefinition<'input, T, I>, GrammarError> {
+        let mut tokenizer = ExpressionTokenizer::new(right_side);
+        if let Some(offset) = tokenizer.unterminated_terminal() {
+            return Err(GrammarError::new(
+                GrammarErrorKind::UnterminatedTerminal,
+                offset,
+            ));
+        }
+        let mut annotations = Vec::new();
+        let mut recover = None;
+        while let Some(ExpressionToken::Annotation(annotation)) = tokenizer.peek_token() {
+            let annotation = annotation.clone();
+            tokenizer.next_token();
+            let offset = tokenizer.current_offset();
+            if !RULE_ANNOTATIONS.contains(&annotation.as_str()) {
+                return Err(GrammarError::new(
+                    GrammarErrorKind::InvalidAnnotation(annotation),
+                    offset,
+                ));
+            }
+            if annotation == "recover" {
+                // The synchronization expression follows in parentheses
+                if tokenizer.next_token() != Some(ExpressionToken::GroupBegin) {
+                    return Err(GrammarError::new(
+                        GrammarErrorKind::InvalidAnnotation(annotation),
+                        offset,
+                    ));
+                }
+                let group_start = tokenizer.current_offset();
+                recover = Some(Self::parse_rule(&mut tokenizer, Some(group_start))?);
+            }
+            annotations.push(annotation);
+        }
+        Ok(RuleDefinition {
+            expression: Self::parse_rule(&mut tokenizer, None)?,
+            annotations,
+            recover,
+        })
+    }
+
+    // group_start is the offset of the opening parenthesis if this call parses a group
+    fn parse_rule(
+        tokenizer: &mut ExpressionTokenizer,
+        group_start: Option<usize>,
+    ) -> Result<Box<dyn ParsingExpression<T, I> + 'input>, GrammarError> {
         let mut sequence = Vec::new();
         let mut choices = Vec::new();
         let mut and_predicate = false;
         let mut not_predicate = false;
+        let mut label: Option<String> = None;
+        let mut after_cut = false;
 
-        loop {
-            if let Some(token) = tokenizer.next_token() {
-                let expr = match token {
-                    ExpressionToken::GroupBegin => Some(Self::parse_rule(tokenizer)),
-                    ExpressionToken::GroupEnd => {
-                        if choices.len() > 0 {
-                            choices.push(
-                                Self::vec_to_expression(sequence).expect("Invalid PEG grammar"),
-                            );
-                            return ChoiceParsingExpression::new(choices);
-                        } else {
-                            return Self::vec_to_expression(sequence).expect("Invalid PEG grammar");
-                        }
-                    }
-                    ExpressionToken::Expression(val) => {
-                        Some(NonTerminalParsingExpression::new(val.as_str()))
-                    }
-                    ExpressionToken::TerminalExpression(val) => {
-                        Some(TerminalParsingExpression::new(val.as_str()))
-                    }
-                    ExpressionToken::TerminalRegexExpression(val) => {
-                        Some(TerminalParsingExpression::new_from_regex(val.as_str()))
+        while let Some(token) = tokenizer.next_token() {
+            let offset = tokenizer.current_offset();
+            let is_quantifier = matches!(
+                token,
+                ExpressionToken::ZeroOrMore
+                    | ExpressionToken::OneOrMore
+                    | ExpressionToken::Optional
+            );
+            if is_quantifier
+                && (sequence.is_empty()
+                    || and_predicate
+                    || not_predicate
+                    || label.is_some()
+                    || after_cut)
+            {
+                return Err(GrammarError::new(
+                    GrammarErrorKind::DanglingQuantifier,
+                    offset,
+                ));
+            }
+            after_cut = false;
+            let expr = match token {
+                ExpressionToken::GroupBegin => Some(Self::parse_rule(tokenizer, Some(offset))?),
+                ExpressionToken::GroupEnd => {
+                    if group_start.is_none() {
+                        return Err(GrammarError::new(
+                            GrammarErrorKind::UnbalancedParentheses,
+                            offset,
+                        ));
                     }
-                    ExpressionToken::Choice => {
-                        choices
-                            .push(Self::vec_to_expression(sequence).expect("Invalid PEG grammar"));
-                        sequence = Vec::new();
-                        None
+                    if label.is_some() {
+                        return Err(GrammarError::new(GrammarErrorKind::InvalidLabel, offset));
                     }
-                    ExpressionToken::ZeroOrMore => {
-                        let child = sequence.remove(sequence.len() - 1); // Panics if invalid grammar
-                        sequence.push(ZeroOrMoreParsingExpression::new(child));
-                        None
+                    return Self::finish_choice(
+                        choices,
+                        sequence,
+                        and_predicate || not_predicate,
+                        offset,
+                    );
+                }
+                ExpressionToken::Expression(val) => {
+                    Some(NonTerminalParsingExpression::new(val.as_str()))
+                }
+                ExpressionToken::TerminalExpression(val) => {
+                    Some(I::literal(val.as_str()).map_err(|kind| GrammarError::new(kind, offset))?)
+                }
+                ExpressionToken::TerminalRegexExpression(val) => {
+                    Some(I::regex(val.as_str()).map_err(|kind| GrammarError::new(kind, offset))?)
+                }
+                ExpressionToken::Choice => {
+                    if and_predicate || not_predicate {
+                        return Err(GrammarError::new(
+                            GrammarErrorKind::DanglingPredicate,
+                            offset,
+                        ));
                     }
-                    ExpressionToken::OneOrMore => {
-                        let child = sequence.remove(sequence.len() - 1); // Panics if invalid grammar
-                        sequence.push(OneOrMoreParsingExpression::new(child));
-                        None
+                    if label.is_some() {
+                        return Err(GrammarError::new(GrammarErrorKind::InvalidLabel, offset));
                     }
-                    ExpressionToken::Optional => {
-                        let child = sequence.remove(sequence.len() - 1); // Panics if invalid grammar
-                        sequence.push(OptionalParsingExpression::new(child));
-                        None
+                    choices.push(Self::vec_to_expression(sequence).ok_or_else(|| {
+                        GrammarError::new(GrammarErrorKind::EmptyAlternative, offset)
+                    })?);
+                    sequence = Vec::new();
+                    None
+                }
+                ExpressionToken::ZeroOrMore => {
+                    let child = sequence.pop().unwrap();
+                    sequence.push(ZeroOrMoreParsingExpression::new(child));
+                    None
+                }
+                ExpressionToken::OneOrMore => {
+                    let child = sequence.pop().unwrap();
+                    sequence.push(OneOrMoreParsingExpression::new(child));
+                    None
+                }
+                ExpressionToken::Optional => {
+                    let child = sequence.pop().unwrap();
+                    sequence.push(OptionalParsingExpression::new(child));
+                    None
+                }
+                ExpressionToken::NotPredicate => {
+                    not_predicate = true;
+                    None
+                }
+                ExpressionToken::AndPredicate => {
+                    and_predicate = true;
+                    None
+                }
+                ExpressionToken::Cut => {
+                    if and_predicate || not_predicate {
+                        return Err(GrammarError::new(
+                            GrammarErrorKind::DanglingPredicate,
+                            offset,
+                        ));
                     }
-                    ExpressionToken::NotPredicate => {
-                        not_predicate = true;
-                        None
+                    if label.is_some() {
+                        return Err(GrammarError::new(GrammarErrorKind::InvalidLabel, offset));
                     }
-                    ExpressionToken::AndPredicate => {
-                        and_predicate = true;
-                        None
+                    // Not an expression of its own, so quantifiers and predicates don't apply
+                    sequence.push(CutParsingExpression::new());
+                    after_cut = true;
+                    continue;
+                }
+                ExpressionToken::Label(name) => {
+                    // A label needs a name and can't be labeled again
+                    if name.is_empty() || label.is_some() {
+                        return Err(GrammarError::new(GrammarErrorKind::InvalidLabel, offset));
                     }
-                    ExpressionToken::None => None,
-                };
+                    label = Some(name);
+                    None
+                }
+                ExpressionToken::Annotation(annotation) => {
+                    // Annotations are only allowed at the start of the rule
+                    return Err(GrammarError::new(
+                        GrammarErrorKind::InvalidAnnotation(annotation),
+                        offset,
+                    ));
+                }
+                ExpressionToken::None => None,
+            };
 
-                if let Some(val) = expr {
-                    if and_predicate {
-                        sequence.push(AndPredicateParsingExpression::new(val));
-                    } else if not_predicate {
-                        sequence.push(NotPredicateParsingExpression::new(val));
-                    } else {
-                        sequence.push(val);
-                    }
-                    not_predicate = false;
-                    and_predicate = false;
+            if let Some(mut val) = expr {
+                // The label binds to the primary expression, quantifiers repeat the labeled one
+                if let Some(name) = label.take() {
+                    val = LabeledParsingExpression::new(name.as_str(), val);
                 }
-            } else {
-                break;
+                if and_predicate {
+                    sequence.push(AndPredicateParsingExpression::new(val));
+                } else if not_predicate {
+                    sequence.push(NotPredicateParsingExpression::new(val));
+                } else {
+                    sequence.push(val);
+                }
+                not_predicate = false;
+                and_predicate = false;
             }
         }
 
-        return if choices.len() >= 1 {
-            choices.push(Self::vec_to_expression(sequence).expect("Invalid PEG grammar"));
-            ChoiceParsingExpression::new(choices)
+        if let Some(offset) = group_start {
+            return Err(GrammarError::new(
+                GrammarErrorKind::UnbalancedParentheses,
+                offset,
+            ));
+        }
+        let offset = tokenizer.current_offset();
+        if label.is_some() {
+            return Err(GrammarError::new(GrammarErrorKind::InvalidLabel, offset));
+        }
+        Self::finish_choice(choices, sequence, and_predicate || not_predicate, offset)
+    }
+
+    fn finish_choice(
+        mut choices: Vec<Box<dyn ParsingExpression<T, I> + 'input>>,
+        sequence: Vec<Box<dyn ParsingExpression<T, I> + 'input>>,
+        dangling_predicate: bool,
+        offset: usize,
+    ) -> Result<Box<dyn ParsingExpression<T, I> + 'input>, GrammarError> {
+        if dangling_predicate {
+            return Err(GrammarError::new(
+                GrammarErrorKind::DanglingPredicate,
+                offset,
+            ));
+        }
+        let last = Self::vec_to_expression(sequence)
+            .ok_or_else(|| GrammarError::new(GrammarErrorKind::EmptyAlternative, offset))?;
+        if choices.is_empty() {
+            Ok(last)
         } else {
-            Self::vec_to_expression(sequence).expect("Invalid PEG grammar")
-        };
+            choices.push(last);
+            Ok(ChoiceParsingExpression::new(choices))
+        }
     }
 
     fn vec_to_expression(
-        mut vec: Vec<Box<dyn ParsingExpression<T>>>,
-    ) -> Option<Box<dyn ParsingExpression<T>>> {
+        mut vec: Vec<Box<dyn ParsingExpression<T, I> + 'input>>,
+    ) -> Option<Box<dyn ParsingExpression<T, I> + 'input>> {
         if !vec.is_empty() {
             if vec.len() > 1 {
                 return Some(SequenceParsingExpression::new(vec));
@@ -619,20 +1920,169 @@ impl<T: 'static> Parser<T> {
                 return Some(vec.remove(0));
             }
         }
-        return None;
+        None
+    }
+}
+
+impl<'input, T: 'input> Parser<'input, T> {
+    pub fn validate(&self, start_non_terminal: &str, code: &'input str) -> bool {
+        let mut tokenizer = CodeTokenizer::new(code);
+        let rule_result = self.run(start_non_terminal, &mut tokenizer, ParseMode::Callbacks);
+        matches!(rule_result, Ok(Some(_))) && tokenizer.is_empty()
+    }
+
+    pub fn parse(&self, start_non_terminal: &str, code: &'input str) -> Result<T, ParseError> {
+        let mut tokenizer = CodeTokenizer::new(code);
+        let rule_result = self.run(start_non_terminal, &mut tokenizer, ParseMode::Callbacks)?;
+        let parsing_result = Self::complete_result(rule_result, &tokenizer)?;
+        Self::callback_result(start_non_terminal, parsing_result, &tokenizer)
+    }
+
+    // Parses from the front of the code, it doesn't have to match all of it
+    pub fn parse_prefix(
+        &self,
+        start_non_terminal: &str,
+        code: &'input str,
+    ) -> Result<(T, usize), ParseError> {
+        self.parse_at(start_non_terminal, code, 0)
+    }
+
+    /* Parses from a byte offset on, without requiring to reach the end of the code.
+     * Returns the offset after the match and the whitespace skipped behind it, parsing can be
+     * resumed from there. An offset that isn't on a character boundary is an InvalidOffset
+     * error at the character before it.
+     */
+    pub fn parse_at(
+        &self,
+        start_non_terminal: &str,
+        code: &'input str,
+        offset: usize,
+    ) -> Result<(T, usize), ParseError> {
+        let mut tokenizer = CodeTokenizer::new(code);
+        if !code.is_char_boundary(offset) {
+            let mut position = offset.min(code.len());
+            while !code.is_char_boundary(position) {
+                position -= 1;
+            }
+            let kind = ParseErrorKind::InvalidOffset(offset);
+            return Err(ParseError::new(kind, position, Vec::new(), &tokenizer));
+        }
+        tokenizer.set_state(offset);
+        self.parse_from(start_non_terminal, &mut tokenizer)
+    }
+}
+
+impl<'input, T: 'input> Parser<'input, T, Bytes> {
+    /* Parses binary data. Terminals match the UTF-8 bytes of strings, byte regexes (the [regex]
+     * of grammar strings is one), byte ranges and integers. Binary formats usually set
+     * Skipper::None, the default skipper skips ASCII whitespace. Errors are at line 1, the
+     * column is the offset + 1.
+     */
+    pub fn parse_bytes(
+        &self,
+        start_non_terminal: &str,
+        bytes: &'input [u8],
+    ) -> Result<T, ParseError> {
+        let mut tokenizer = CodeTokenizer::from_bytes(bytes);
+        let rule_result = self.run(start_non_terminal, &mut tokenizer, ParseMode::Callbacks)?;
+        let parsing_result = Self::complete_result(rule_result, &tokenizer)?;
+        Self::callback_result(start_non_terminal, parsing_result, &tokenizer)
+    }
+
+    // Like parse_prefix, e.g. to read one frame after another
+    pub fn parse_bytes_prefix(
+        &self,
+        start_non_terminal: &str,
+        bytes: &'input [u8],
+    ) -> Result<(T, usize), ParseError> {
+        self.parse_from(start_non_terminal, &mut CodeTokenizer::from_bytes(bytes))
+    }
+}
+
+impl<'input, T: 'input, K> Parser<'input, T, Tokens<K>> {
+    // Parses the tokens of an existing lexer, terminals are TokenParsingExpressions
+    pub fn parse_tokens(
+        &self,
+        start_non_terminal: &str,
+        tokens: &'input [K],
+    ) -> Result<T, ParseError> {
+        let mut tokenizer = TokenStream::new(tokens);
+        let rule_result = self.run(start_non_terminal, &mut tokenizer, ParseMode::Callbacks)?;
+        let parsing_result = Self::complete_result(rule_result, &tokenizer)?;
+        Self::callback_result(start_non_terminal, parsing_result, &tokenizer)
+    }
+
+    // Like parse_prefix, the end is the index of the first token that wasn't parsed
+    pub fn parse_tokens_prefix(
+        &self,
+        start_non_terminal: &str,
+        tokens: &'input [K],
+    ) -> Result<(T, usize), ParseError> {
+        self.parse_from(start_non_terminal, &mut TokenStream::new(tokens))
+    }
+}
+
+impl<'input, T: 'input> Parser<'input, T, Stream> {
+    /* Reads the input on demand, only the lines the parser may still go back to are kept.
+     * Callbacks can read the text of their rule, unless a repetition of rules or a recursive
+     * rule can be reached from it: such a rule may span the whole input, which isn't kept for it
+     * (streamed_text is None then). Results inside of it can still read theirs.
+     * A Read can be wrapped in a BufReader.
+     */
+    pub fn parse_reader(
+        &self,
+        start_non_terminal: &str,
+        reader: impl BufRead + 'input,
+    ) -> Result<T, ParseError> {
+        let mut tokenizer = StreamTokenizer::new(reader);
+        let rule_result = self.run(start_non_terminal, &mut tokenizer, ParseMode::Callbacks);
+        if let Some(err) = tokenizer.take_error() {
+            let position = tokenizer.bytes_read();
+            return Err(ParseError::new(
+                ParseErrorKind::Io(err.to_string()),
+                position,
+                Vec::new(),
+                &tokenizer,
+            ));
+        }
+        let parsing_result = Self::complete_result(rule_result?, &tokenizer)?;
+        Self::callback_result(start_non_terminal, parsing_result, &tokenizer)
+    }
+}
+
+impl<'input, T: Clone + 'input, I: Input + 'input> Parser<'input, T, I> {
+    /* Packrat mode caches the result of every rule at every input position, so backtracking
+     * never parses the same rule at the same position twice. Callback results are cloned out
+     * of the cache, which is why this needs T: Clone.
+     */
+    pub fn set_packrat(&mut self, enabled: bool) {
+        self.cloner = Some(T::clone);
+        self.packrat = enabled;
+    }
+
+    /* Left recursive rules (Sum -> Sum '+' Product | Product) are parsed by growing a seed:
+     * the recursive call first fails, then returns the previous result, until the match stops
+     * getting longer. Without this, a parse that reaches a left recursive rule fails with
+     * ParseErrorKind::LeftRecursion instead of overflowing the stack.
+     */
+    pub fn set_left_recursion(&mut self, enabled: bool) {
+        self.cloner = Some(T::clone);
+        self.left_recursion = enabled;
     }
 }
 
-impl<T> fmt::Display for Parser<T> {
+impl<'input, T, I: Input> fmt::Display for Parser<'input, T, I> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut res: fmt::Result = fmt::Result::Ok(());
         for (left_side, right_side) in &self.rules {
-            res = res.and(write!(
-                f,
-                "{} -> {}",
-                left_side,
-                right_side.expression.dump()
-            ));
+            res = res.and(write!(f, "{} -> ", left_side));
+            for annotation in right_side.annotations() {
+                res = res.and(write!(f, "@{} ", annotation));
+            }
+            if let Some(ref recover) = right_side.recover {
+                res = res.and(write!(f, "@recover({}) ", recover.dump()));
+            }
+            res = res.and(write!(f, "{}", right_side.expression.dump()));
         }
         res
     }
@@ -0,0 +1,38 @@
+use crate::code_tokenizer::CodeTokenizer;
+use std::ops::Range;
+
+/* A byte range of the code. Lines and columns are looked up in the line index of the
+ * tokenizer, which is only built the first time any span asks for them.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    // Both 1-based, the column is counted in characters
+    pub fn start_line_column(&self, tokenizer: &CodeTokenizer) -> (usize, usize) {
+        tokenizer.line_column(self.start)
+    }
+
+    pub fn end_line_column(&self, tokenizer: &CodeTokenizer) -> (usize, usize) {
+        tokenizer.line_column(self.end)
+    }
+}
@@ -0,0 +1,515 @@
+use crate::input::{Failures, TerminalRegex, Tokenizer};
+use regex_automata::hybrid::dfa::{Cache, DFA};
+use regex_automata::util::{start, syntax};
+use regex_automata::{nfa::thompson, Anchored};
+use std::collections::HashMap;
+use std::io;
+use std::io::BufRead;
+use std::str;
+
+/* Text read from a BufRead on demand, the position is a byte offset into the whole input.
+ * Whole lines before the oldest position the parser still holds (see Tokenizer::hold) and
+ * before the current position are discarded, so only the lines the parser may still read
+ * stay in memory. A failed expression may go back to discarded input, nothing matches there.
+ */
+pub struct StreamTokenizer<'input> {
+    reader: Box<dyn BufRead + 'input>,
+    // The input from offset on, the buffer always starts at the start of a line
+    buffer: Vec<u8>,
+    offset: usize,
+    // Lines that were discarded
+    lines: usize,
+    // Set when the reader has no more data or failed
+    exhausted: bool,
+    error: Option<io::Error>,
+    position: usize,
+    holds: Vec<usize>,
+    failures: Failures,
+    skip_whitespace: bool,
+    // Lazy DFAs of the regexes by pattern, None if one couldn't be built
+    automata: HashMap<String, Option<(DFA, Cache)>>,
+}
+
+impl<'input> StreamTokenizer<'input> {
+    pub fn new(reader: impl BufRead + 'input) -> StreamTokenizer<'input> {
+        let mut tokenizer = StreamTokenizer {
+            reader: Box::new(reader),
+            buffer: Vec::new(),
+            offset: 0,
+            lines: 0,
+            exhausted: false,
+            error: None,
+            position: 0,
+            holds: Vec::new(),
+            failures: Failures::default(),
+            skip_whitespace: true,
+            automata: HashMap::new(),
+        };
+        tokenizer.advance(0);
+        tokenizer
+    }
+
+    // Whether everything was read and consumed
+    pub fn is_empty(&self) -> bool {
+        self.exhausted && self.end() <= self.position
+    }
+
+    // How many bytes of the input are kept in memory
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn bytes_read(&self) -> usize {
+        self.end()
+    }
+
+    // The error the reader failed with, the input ends where it failed
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // Whether match_string and match_regex skip whitespace before and after the match
+    pub fn set_skip_whitespace(&mut self, enabled: bool) {
+        self.skip_whitespace = enabled;
+    }
+
+    pub fn match_string(&mut self, string: &str) -> bool {
+        self.match_bytes(string.as_bytes())
+    }
+
+    pub fn match_bytes(&mut self, bytes: &[u8]) -> bool {
+        if bytes.is_empty() {
+            return true;
+        }
+        self.skip_implicit_whitespaces();
+        if self.discarded() {
+            return false;
+        }
+        let end = self.position + bytes.len();
+        self.fill(end);
+        let start = self.index(self.position);
+        if self.buffer[start..].starts_with(bytes) && self.is_boundary(end) {
+            self.advance(end);
+            true
+        } else {
+            false
+        }
+    }
+
+    // A single byte from low to high, both inclusive
+    pub fn match_byte_range(&mut self, low: u8, high: u8) -> bool {
+        self.skip_implicit_whitespaces();
+        if self.discarded() {
+            return false;
+        }
+        let start = self.index(self.position);
+        match self.buffer.get(start).copied() {
+            Some(byte) if (low..=high).contains(&byte) && self.is_boundary(self.position + 1) => {
+                self.advance(self.position + 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn match_any_bytes(&mut self, count: usize) -> bool {
+        self.skip_implicit_whitespaces();
+        if self.discarded() {
+            return false;
+        }
+        let end = self.position + count;
+        if self.fill(end) >= end && self.is_boundary(end) {
+            self.advance(end);
+            true
+        } else {
+            false
+        }
+    }
+
+    /* Regexes see the input up to the end of the line. As long as they could still match
+     * with more input (a match may start here or get longer), they're tried again with the
+     * following lines.
+     */
+    pub fn match_regex(&mut self, regex: &TerminalRegex) -> bool {
+        self.skip_implicit_whitespaces();
+        self.match_lines(regex.as_str(), |buffer, start| {
+            let text = valid_prefix(buffer);
+            if start > text.len() {
+                return None;
+            }
+            regex.match_at(text.as_bytes(), start)
+        })
+    }
+
+    pub fn match_bytes_regex(&mut self, regex: &TerminalRegex) -> bool {
+        self.skip_implicit_whitespaces();
+        self.match_lines(regex.as_str(), |buffer, start| {
+            regex.match_at(buffer, start)
+        })
+    }
+
+    // find returns the end of the match that starts at the index start of the buffer
+    fn match_lines(&mut self, pattern: &str, find: impl Fn(&[u8], usize) -> Option<usize>) -> bool {
+        if self.discarded() {
+            return false;
+        }
+        let mut lines = 1;
+        loop {
+            let end = self.lines_end(lines);
+            let start = self.index(self.position);
+            let window = end - self.offset;
+            let found = find(&self.buffer[..window], start);
+            // Without an automaton only a match that reaches the end is tried again
+            let open = self
+                .could_match(pattern, start, window)
+                .unwrap_or(found == Some(window));
+            if open && self.fill(end + 1) > end {
+                lines *= 2;
+                continue;
+            }
+            match found {
+                Some(match_end) => {
+                    let match_end = self.offset + match_end;
+                    if !self.is_boundary(match_end) {
+                        return false;
+                    }
+                    self.advance(match_end);
+                    return true;
+                }
+                None => return false,
+            }
+        }
+    }
+
+    /* Whether the regex could match from the buffer index from on, if there was more input
+     * after the index to. None if the lazy DFA can't tell, e.g. for a Unicode word boundary
+     * next to non-ASCII text.
+     */
+    fn could_match(&mut self, pattern: &str, from: usize, to: usize) -> Option<bool> {
+        if !self.automata.contains_key(pattern) {
+            // The patterns are valid for regex or regex::bytes, so they may match invalid UTF-8
+            let automaton = DFA::builder()
+                .configure(DFA::config().unicode_word_boundary(true))
+                .syntax(syntax::Config::new().utf8(false))
+                .thompson(thompson::Config::new().utf8(false))
+                .build(pattern)
+                .ok()
+                .map(|dfa| {
+                    let cache = dfa.create_cache();
+                    (dfa, cache)
+                });
+            self.automata.insert(pattern.to_string(), automaton);
+        }
+        let (dfa, cache) = self.automata.get_mut(pattern)?.as_mut()?;
+        // The buffer starts at the start of a line
+        let before = match from {
+            0 if self.offset == 0 => None,
+            0 => Some(b'\n'),
+            _ => Some(self.buffer[from - 1]),
+        };
+        let config = start::Config::new()
+            .anchored(Anchored::Yes)
+            .look_behind(before);
+        let mut state = dfa.start_state(cache, &config).ok()?;
+        for byte in &self.buffer[from..to] {
+            if state.is_dead() {
+                return Some(false);
+            }
+            if state.is_quit() {
+                return None;
+            }
+            state = dfa.next_state(cache, state, *byte).ok()?;
+        }
+        if state.is_quit() {
+            return None;
+        }
+        Some(!state.is_dead())
+    }
+
+    // The end of the count lines from the position on, or of the input
+    fn lines_end(&mut self, count: usize) -> usize {
+        let mut end = self.position;
+        let mut found = 0;
+        loop {
+            match self.buffer[end - self.offset..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+            {
+                Some(newline) => {
+                    end += newline + 1;
+                    found += 1;
+                    if found == count {
+                        return end;
+                    }
+                }
+                None => {
+                    end = self.end();
+                    if self.fill(end + 1) == end {
+                        return end;
+                    }
+                }
+            }
+        }
+    }
+
+    fn skip_implicit_whitespaces(&mut self) {
+        if self.skip_whitespace {
+            self.skip_whitespaces();
+        }
+    }
+
+    // Skips unicode whitespace, reading as much as it takes
+    pub fn skip_whitespaces(&mut self) {
+        if self.discarded() {
+            return;
+        }
+        loop {
+            let start = self.index(self.position);
+            let rest = valid_prefix(&self.buffer[start..]);
+            let trimmed = rest.trim_start_matches(char::is_whitespace);
+            let skipped = rest.len() - trimmed.len();
+            let done = !trimmed.is_empty();
+            if skipped == 0 {
+                return;
+            }
+            self.advance(self.position + skipped);
+            if done {
+                return;
+            }
+        }
+    }
+
+    // Moves past the next character, false at the end of the input
+    pub fn skip_char(&mut self) -> bool {
+        if self.discarded() {
+            return false;
+        }
+        let start = self.index(self.position);
+        let rest = &self.buffer[start..];
+        if rest.is_empty() {
+            return false;
+        }
+        // A byte that is no UTF-8 counts as a character
+        let len = valid_prefix(rest)
+            .chars()
+            .next()
+            .map_or(1, |ch| ch.len_utf8());
+        self.advance(self.position + len);
+        true
+    }
+
+    pub fn get_state(&self) -> usize {
+        self.position
+    }
+
+    // Going back to discarded input is fine, as long as it is not read again
+    pub fn set_state(&mut self, state: usize) {
+        assert!(
+            state <= self.end(),
+            "State {} is behind the input that was read!",
+            state
+        );
+        self.advance(state);
+    }
+
+    // Borrowed from the buffer, None if the start was already discarded
+    pub fn get_substr(&self, start: usize, end: usize) -> Option<&str> {
+        self.get_bytes(start, end)
+            .map(|bytes| str::from_utf8(bytes).expect("The input is not UTF-8!"))
+    }
+
+    pub fn get_bytes(&self, start: usize, end: usize) -> Option<&[u8]> {
+        if start < self.offset {
+            return None;
+        }
+        Some(&self.buffer[self.index(start)..self.index(end)])
+    }
+
+    /* Line and column (in characters) of a byte offset, both start at 1.
+     * Discarded offsets are reported at the start of the buffer.
+     */
+    pub fn line_column(&self, position: usize) -> (usize, usize) {
+        let before = &self.buffer[..self.clamp(position) - self.offset];
+        let line_start = before
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |newline| newline + 1);
+        let newlines = before.iter().filter(|byte| **byte == b'\n').count();
+        (
+            self.lines + newlines + 1,
+            String::from_utf8_lossy(&before[line_start..])
+                .chars()
+                .count()
+                + 1,
+        )
+    }
+
+    // The line around the offset, as far as it was read
+    pub fn line_at(&self, position: usize) -> &str {
+        let position = self.clamp(position) - self.offset;
+        let start = self.buffer[..position]
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |newline| newline + 1);
+        let end = self.buffer[position..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(self.buffer.len(), |newline| position + newline);
+        valid_prefix(&self.buffer[start..end]).trim_end_matches('\r')
+    }
+
+    pub fn hold(&mut self, position: usize) {
+        self.holds.push(position);
+    }
+
+    pub fn release(&mut self, position: usize) {
+        if let Some(index) = self.holds.iter().rposition(|held| *held == position) {
+            self.holds.remove(index);
+        }
+    }
+
+    // Moves to the position, with the character there read unless the input ends before it
+    fn advance(&mut self, position: usize) {
+        self.position = position;
+        self.fill(position + 4);
+    }
+
+    fn end(&self) -> usize {
+        self.offset + self.buffer.len()
+    }
+
+    // Only failure reporting gets here, after a failed expression went back to its start
+    fn discarded(&self) -> bool {
+        self.position < self.offset
+    }
+
+    fn index(&self, position: usize) -> usize {
+        assert!(
+            position >= self.offset,
+            "Position {} was already discarded!",
+            position
+        );
+        position - self.offset
+    }
+
+    fn clamp(&self, position: usize) -> usize {
+        position.clamp(self.offset, self.end())
+    }
+
+    // Text positions can't be inside of a character
+    fn is_boundary(&mut self, position: usize) -> bool {
+        self.fill(position + 1);
+        match self.buffer.get(position - self.offset) {
+            Some(byte) => (*byte as i8) >= -0x40,
+            None => true,
+        }
+    }
+
+    // Reads until the input up to end is buffered or the reader is exhausted, returns the end
+    fn fill(&mut self, end: usize) -> usize {
+        while self.end() < end && !self.exhausted {
+            self.discard();
+            match self.reader.fill_buf() {
+                Ok([]) => self.exhausted = true,
+                Ok(data) => {
+                    let len = data.len();
+                    self.buffer.extend_from_slice(data);
+                    self.reader.consume(len);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.error = Some(err);
+                    self.exhausted = true;
+                }
+            }
+        }
+        self.end()
+    }
+
+    // Drops the lines nobody needs anymore, once they are at least half of the buffer
+    fn discard(&mut self) {
+        let keep = self
+            .holds
+            .iter()
+            .fold(self.position, |keep, held| keep.min(*held));
+        let keep = keep.saturating_sub(self.offset);
+        let cut = match self.buffer[..keep].iter().rposition(|byte| *byte == b'\n') {
+            Some(newline) => newline + 1,
+            None => return,
+        };
+        if cut * 2 < self.buffer.len() {
+            return;
+        }
+        self.lines += self.buffer[..cut]
+            .iter()
+            .filter(|byte| **byte == b'\n')
+            .count();
+        self.buffer.drain(..cut);
+        self.offset += cut;
+    }
+}
+
+// The input is read in chunks, they can end inside of a character
+fn valid_prefix(bytes: &[u8]) -> &str {
+    match str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(err) => str::from_utf8(&bytes[..err.valid_up_to()]).unwrap(),
+    }
+}
+
+impl<'input> Tokenizer<'input> for StreamTokenizer<'input> {
+    fn get_state(&self) -> usize {
+        StreamTokenizer::get_state(self)
+    }
+    fn set_state(&mut self, state: usize) {
+        StreamTokenizer::set_state(self, state)
+    }
+    fn is_empty(&self) -> bool {
+        StreamTokenizer::is_empty(self)
+    }
+    fn skip_whitespaces(&mut self) {
+        StreamTokenizer::skip_whitespaces(self)
+    }
+    fn skip_char(&mut self) -> bool {
+        StreamTokenizer::skip_char(self)
+    }
+    fn match_string(&mut self, string: &str) -> bool {
+        StreamTokenizer::match_string(self, string)
+    }
+    fn match_regex(&mut self, regex: &TerminalRegex) -> bool {
+        StreamTokenizer::match_regex(self, regex)
+    }
+    fn match_bytes(&mut self, bytes: &[u8]) -> bool {
+        StreamTokenizer::match_bytes(self, bytes)
+    }
+    fn match_byte_range(&mut self, low: u8, high: u8) -> bool {
+        StreamTokenizer::match_byte_range(self, low, high)
+    }
+    fn match_any_bytes(&mut self, count: usize) -> bool {
+        StreamTokenizer::match_any_bytes(self, count)
+    }
+    fn match_bytes_regex(&mut self, regex: &TerminalRegex) -> bool {
+        StreamTokenizer::match_bytes_regex(self, regex)
+    }
+    fn line_column(&self, position: usize) -> (usize, usize) {
+        StreamTokenizer::line_column(self, position)
+    }
+    fn line_at(&self, position: usize) -> &str {
+        StreamTokenizer::line_at(self, position)
+    }
+    fn set_skip_whitespace(&mut self, enabled: bool) {
+        StreamTokenizer::set_skip_whitespace(self, enabled)
+    }
+    fn hold(&mut self, position: usize) {
+        StreamTokenizer::hold(self, position)
+    }
+    fn release(&mut self, position: usize) {
+        StreamTokenizer::release(self, position)
+    }
+    fn failures(&self) -> &Failures {
+        &self.failures
+    }
+    fn failures_mut(&mut self) -> &mut Failures {
+        &mut self.failures
+    }
+}
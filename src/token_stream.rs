@@ -0,0 +1,59 @@
+use crate::input::{Failures, Tokenizer};
+
+// The tokens of an existing lexer, the position is the index of the next token
+pub struct TokenStream<'input, K> {
+    tokens: &'input [K],
+    position: usize,
+    failures: Failures,
+}
+
+impl<'input, K> TokenStream<'input, K> {
+    pub fn new(tokens: &'input [K]) -> TokenStream<'input, K> {
+        TokenStream {
+            tokens,
+            position: 0,
+            failures: Failures::default(),
+        }
+    }
+
+    // Moves past the next token if the predicate accepts it
+    pub fn match_token<P: Fn(&K) -> bool + ?Sized>(&mut self, predicate: &P) -> bool {
+        match self.tokens.get(self.position) {
+            Some(token) if predicate(token) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get_tokens(&self, start: usize, end: usize) -> &'input [K] {
+        &self.tokens[start..end]
+    }
+}
+
+impl<'input, K> Tokenizer<'input> for TokenStream<'input, K> {
+    fn get_state(&self) -> usize {
+        self.position
+    }
+    fn set_state(&mut self, state: usize) {
+        assert!(
+            state <= self.tokens.len(),
+            "State {} is behind the last token!",
+            state
+        );
+        self.position = state;
+    }
+    fn is_empty(&self) -> bool {
+        self.tokens.len() <= self.position
+    }
+    fn skip_char(&mut self) -> bool {
+        self.match_token(&|_: &K| true)
+    }
+    fn failures(&self) -> &Failures {
+        &self.failures
+    }
+    fn failures_mut(&mut self) -> &mut Failures {
+        &mut self.failures
+    }
+}
@@ -0,0 +1,70 @@
+use crate::span::Span;
+use std::fmt::Write;
+
+/* Hooks that are called around every ParsingExpression::matches while a tracer is set with
+ * Parser::set_tracer. rule is the rule the expression belongs to (empty for the start rule),
+ * expression is its dump and offset the position it starts at.
+ * The skipper rule is not traced.
+ */
+pub trait Tracer {
+    fn enter(&mut self, _rule: &str, _expression: &str, _offset: usize) {}
+    // span covers the matched tokens
+    fn success(&mut self, _rule: &str, _expression: &str, _span: Span) {}
+    fn failure(&mut self, _rule: &str, _expression: &str, _offset: usize) {}
+    fn exit(&mut self, _rule: &str, _expression: &str, _offset: usize) {}
+}
+
+/* Renders the parse attempt as an indented trace:
+ *     Sum at 0
+ *       Product (('+' | '-') Product)* at 0
+ *         Product at 0
+ *         ...
+ */
+#[derive(Default)]
+pub struct TracePrinter {
+    trace: String,
+    depth: usize,
+}
+
+impl TracePrinter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trace(&self) -> &str {
+        &self.trace
+    }
+
+    pub fn clear(&mut self) {
+        self.trace.clear();
+        self.depth = 0;
+    }
+
+    fn indent(&mut self, depth: usize) {
+        for _ in 0..depth {
+            self.trace.push_str("  ");
+        }
+    }
+}
+
+impl Tracer for TracePrinter {
+    fn enter(&mut self, _rule: &str, expression: &str, offset: usize) {
+        self.indent(self.depth);
+        writeln!(self.trace, "{} at {}", expression, offset).unwrap();
+        self.depth += 1;
+    }
+
+    fn success(&mut self, _rule: &str, _expression: &str, span: Span) {
+        self.indent(self.depth - 1);
+        writeln!(self.trace, "=> matched {}..{}", span.start, span.end).unwrap();
+    }
+
+    fn failure(&mut self, _rule: &str, _expression: &str, _offset: usize) {
+        self.indent(self.depth - 1);
+        self.trace.push_str("=> failed\n");
+    }
+
+    fn exit(&mut self, _rule: &str, _expression: &str, _offset: usize) {
+        self.depth -= 1;
+    }
+}
@@ -0,0 +1,162 @@
+use crate::code_tokenizer::CodeTokenizer;
+use crate::error::{ParseError, ParseErrorKind};
+use crate::parser::{ParseMode, Parser};
+use crate::span::Span;
+use std::rc::Rc;
+
+/* One matched rule of a concrete syntax tree. Nodes only know their span, the text is read
+ * from the parsed code. Children are shared, packrat mode reuses them between results.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct CSTNode {
+    pub rule: String,
+    pub span: Span,
+    // The rules matched inside of this rule, in input order
+    pub children: Vec<Rc<CSTNode>>,
+    // Set for the input a rule skipped when it recovered from this error
+    pub error: Option<ParseError>,
+}
+
+impl CSTNode {
+    // The matched text, code is what was passed to Parser::parse_tree
+    pub fn text<'input>(&self, code: &'input str) -> &'input str {
+        &code[self.span.start..self.span.end]
+    }
+
+    // All nodes of the tree in pre-order, starting with this one
+    pub fn descendants(&self) -> Vec<&CSTNode> {
+        let mut nodes = vec![self];
+        for child in &self.children {
+            nodes.extend(child.descendants());
+        }
+        nodes
+    }
+
+    // The errors of all error nodes in the tree, in input order
+    pub fn errors(&self) -> Vec<&ParseError> {
+        self.descendants()
+            .into_iter()
+            .filter_map(|node| node.error.as_ref())
+            .collect()
+    }
+}
+
+/* A node of the abstract syntax tree, the kind is the name of the rule that matched.
+ * Rules are shaped into the AST by annotations in the grammar:
+ *     @drop     the rule is left out, e.g. Comma <- @drop ','
+ *     @inline   a node with a single child is replaced by the child, e.g. Value <- @inline Number | Group
+ *     @flatten  children of the same kind are merged into the node, e.g. List <- @flatten Item (Comma List)?
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ASTNode {
+    pub kind: String,
+    pub span: Span,
+    pub children: Vec<ASTNode>,
+    pub error: Option<ParseError>,
+}
+
+impl ASTNode {
+    // Like CSTNode::text, code is what was passed to Parser::parse_ast
+    pub fn text<'input>(&self, code: &'input str) -> &'input str {
+        &code[self.span.start..self.span.end]
+    }
+}
+
+/* The concrete syntax tree contains a node for every rule that took part in the match,
+ * terminals are only present as the text of their rule. Callbacks are not called.
+ */
+impl<'input, T: 'input> Parser<'input, T> {
+    pub fn parse_tree(
+        &self,
+        start_non_terminal: &str,
+        code: &'input str,
+    ) -> Result<CSTNode, ParseError> {
+        let mut tokenizer = CodeTokenizer::new(code);
+        let rule_result = self.run(start_non_terminal, &mut tokenizer, ParseMode::Tree)?;
+        let parsing_result = Self::complete_result(rule_result, &tokenizer)?;
+        Ok(Self::unshare(parsing_result.node.unwrap()))
+    }
+
+    /* Like parse_tree, but rules annotated with @recover(...) that fail after matching some of
+     * the input skip to their synchronization expression and continue, e.g.
+     *     Statement <- @recover(';') Name '=' Value ';'
+     * The skipped input becomes an error node. All errors are returned in input order, the tree
+     * is missing only if the start rule didn't match at all.
+     */
+    pub fn parse_tree_recovering(
+        &self,
+        start_non_terminal: &str,
+        code: &'input str,
+    ) -> (Option<CSTNode>, Vec<ParseError>) {
+        let mut tokenizer = CodeTokenizer::new(code);
+        let rule_result = self.run(start_non_terminal, &mut tokenizer, ParseMode::Recovering);
+        match rule_result {
+            Err(error) => (None, vec![error]),
+            Ok(None) => {
+                let error = Self::error_at_farthest_failure(ParseErrorKind::NoMatch, 0, &tokenizer);
+                (None, vec![error])
+            }
+            Ok(Some(parsing_result)) => {
+                let tree = Self::unshare(parsing_result.node.unwrap());
+                let mut errors: Vec<_> = tree.errors().into_iter().cloned().collect();
+                if !tokenizer.is_empty() {
+                    errors.push(Self::error_at_farthest_failure(
+                        ParseErrorKind::UnparsedInput,
+                        parsing_result.parsed_string_end,
+                        &tokenizer,
+                    ));
+                }
+                (Some(tree), errors)
+            }
+        }
+    }
+
+    pub fn parse_ast(
+        &self,
+        start_non_terminal: &str,
+        code: &'input str,
+    ) -> Result<ASTNode, ParseError> {
+        let tree = self.parse_tree(start_non_terminal, code)?;
+        // The start rule is always kept, even if it is dropped or inlined elsewhere
+        Ok(self.build_ast(&tree))
+    }
+
+    // The cache is gone after the parse, so the root is usually not shared anymore
+    fn unshare(node: Rc<CSTNode>) -> CSTNode {
+        Rc::try_unwrap(node).unwrap_or_else(|node| (*node).clone())
+    }
+
+    fn build_ast(&self, node: &CSTNode) -> ASTNode {
+        let flatten = self.rules[&node.rule].flatten;
+        let mut children = Vec::new();
+        for child in &node.children {
+            if let Some(child) = self.shape_ast(child) {
+                if flatten && child.kind == node.rule {
+                    children.extend(child.children);
+                } else {
+                    children.push(child);
+                }
+            }
+        }
+        ASTNode {
+            kind: node.rule.clone(),
+            span: node.span,
+            children,
+            error: node.error.clone(),
+        }
+    }
+
+    // None if the node is dropped
+    fn shape_ast(&self, node: &CSTNode) -> Option<ASTNode> {
+        let rule = &self.rules[&node.rule];
+        if rule.drop {
+            return None;
+        }
+        let inline = rule.inline;
+        let mut ast = self.build_ast(node);
+        if inline && ast.children.len() == 1 {
+            ast = ast.children.pop().unwrap();
+        }
+        Some(ast)
+    }
+}
@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod analysis {
+    use red_peg::analysis::GrammarDiagnostic;
+    use red_peg::parser::*;
+
+    #[test]
+    fn check_grammar() {
+        let mut parser: Parser<()> = Parser::from_grammar_str(
+            "
+            Start <- Item (',' Item)* Trailer?
+            Item <- Number | Name
+            Number <- [0-9]+
+            Unused <- Start Other
+        ",
+        )
+        .unwrap();
+        assert!(parser.try_add_rule_str("Item", "'x'", None).is_err());
+
+        assert_eq!(
+            parser.check("Start"),
+            vec![
+                GrammarDiagnostic::UndefinedRule {
+                    rule: "Name".to_string(),
+                    used_in: Some("Item".to_string()),
+                },
+                GrammarDiagnostic::UndefinedRule {
+                    rule: "Trailer".to_string(),
+                    used_in: Some("Start".to_string()),
+                },
+                GrammarDiagnostic::UnreachableRule {
+                    rule: "Unused".to_string(),
+                },
+                GrammarDiagnostic::DuplicateRule {
+                    rule: "Item".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            format!("{}", parser.check("Start")[0]),
+            "rule Name is used in rule Item, but not defined"
+        );
+
+        assert_eq!(
+            parser.check("Missing")[0],
+            GrammarDiagnostic::UndefinedRule {
+                rule: "Missing".to_string(),
+                used_in: None,
+            }
+        );
+    }
+
+    #[test]
+    fn check_valid_grammar() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Start", "List | '(' Start ')'", None);
+        parser.add_rule_str("List", "'x' (',' List)?", None);
+        assert!(parser.check("Start").is_empty());
+        assert_eq!(parser.check("List").len(), 1);
+
+        parser.add_rule_str("Spacing", "[\\s]*", None);
+        assert_eq!(parser.check("Start").len(), 1);
+        parser.set_skipper(Skipper::Rule("Spacing".to_string()));
+        assert!(parser.check("Start").is_empty());
+    }
+
+    #[test]
+    fn nullable_repetitions() {
+        let parser: Parser<()> = Parser::from_grammar_str(
+            "
+            Start <- (Opt 'a'?)* Loop
+            Opt <- 'b'?
+            Loop <- ('' | 'c')+ [d]*
+            Fine <- (Opt 'a')* [e]+
+        ",
+        )
+        .unwrap();
+        let mut nullable: Vec<_> = parser.nullable_rules().into_iter().collect();
+        nullable.sort_unstable();
+        assert_eq!(nullable, vec!["Loop", "Opt", "Start"]);
+
+        let repetitions: Vec<_> = parser
+            .check("Start")
+            .into_iter()
+            .filter(|d| matches!(d, GrammarDiagnostic::NullableRepetition { .. }))
+            .collect();
+        assert_eq!(
+            repetitions,
+            vec![
+                GrammarDiagnostic::NullableRepetition {
+                    rule: "Loop".to_string(),
+                    expression: "('' | 'c')+".to_string(),
+                },
+                GrammarDiagnostic::NullableRepetition {
+                    rule: "Start".to_string(),
+                    expression: "(Opt 'a'?)*".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn left_recursive_cycles() {
+        let mut parser: Parser<()> = Parser::from_grammar_str(
+            "
+            Start <- Sum A
+            Sum <- Sum '+' N | N
+            N <- [0-9]+
+            A <- B 'x'
+            B <- Opt A | 'y'
+            Opt <- 'o'?
+            C <- 'c' C
+        ",
+        )
+        .unwrap();
+        assert_eq!(
+            parser.check("Start"),
+            vec![
+                GrammarDiagnostic::UnreachableRule {
+                    rule: "C".to_string(),
+                },
+                GrammarDiagnostic::LeftRecursion {
+                    rules: vec!["A".to_string(), "B".to_string()],
+                },
+                GrammarDiagnostic::LeftRecursion {
+                    rules: vec!["Sum".to_string()],
+                },
+            ]
+        );
+        parser.set_left_recursion(true);
+        assert_eq!(parser.check("Start").len(), 1);
+    }
+}
@@ -1,15 +1,19 @@
 #[cfg(test)]
 mod code_tokenizer {
     use red_peg::code_tokenizer::*;
-    use regex::Regex;
+    use red_peg::input::TerminalRegex;
 
     #[test]
-    #[should_panic]
-    fn state_system_panic() {
+    fn state_system_misuse() {
         let mut t = CodeTokenizer::new("Hallo Welt!");
         t.push_state();
+        assert!(t.match_string("Hallo"));
         t.pop_state();
         t.pop_state();
+        assert_eq!(t.get_state(), 0);
+        assert!(t.match_string("Hallo"));
+        assert_eq!(t.update_state(), 6);
+        assert!(t.only_one_state_left());
     }
 
     #[test]
@@ -26,15 +30,95 @@ mod code_tokenizer {
     fn tokenize2() {
         let mut t = CodeTokenizer::new("52 Number");
         t.push_state();
-        assert!(t.match_regex(&Regex::new(r"[\d]").unwrap()));
-        assert!(t.match_regex(&Regex::new(r"[\d]").unwrap()));
+        assert!(t.match_regex(&TerminalRegex::new(r"[\d]").unwrap()));
+        assert!(t.match_regex(&TerminalRegex::new(r"[\d]").unwrap()));
         t.push_state();
         assert!(t.match_string("Number"));
         t.pop_state();
         assert!(t.match_string("Number"));
         t.pop_state();
-        assert!(!t.match_regex(&Regex::new(r"[a-z]").unwrap()));
-        assert!(t.match_regex(&Regex::new(r"[\d]").unwrap()));
-        assert!(t.match_regex(&Regex::new(r"[\d]").unwrap()));
+        assert!(!t.match_regex(&TerminalRegex::new(r"[a-z]").unwrap()));
+        assert!(t.match_regex(&TerminalRegex::new(r"[\d]").unwrap()));
+        assert!(t.match_regex(&TerminalRegex::new(r"[\d]").unwrap()));
+    }
+
+    #[test]
+    fn unicode_german() {
+        let mut t = CodeTokenizer::new("Grüße  aus Köln\u{a0}und Düsseldorf");
+        assert!(t.match_string("Grüße"));
+        assert_eq!(t.get_state(), 9);
+        assert!(t.match_regex(&TerminalRegex::new(r"\w+").unwrap()));
+        assert!(t.match_string("Köln"));
+        // The no-break space is whitespace too
+        assert!(t.match_string("und"));
+        assert!(!t.match_string("Dü "));
+        assert!(!t.match_string("D\u{fc}sseldorfer"));
+        assert!(t.match_regex(&TerminalRegex::new(r"D[a-zäöü]+").unwrap()));
+        assert!(t.is_empty());
+        assert_eq!(t.line_column(t.get_state()), (1, 31));
+    }
+
+    #[test]
+    fn unicode_japanese() {
+        let mut t = CodeTokenizer::new("東京\u{3000}は 日本の首都です");
+        assert!(!t.match_string("京"));
+        assert!(t.match_string("東京"));
+        // The ideographic space counts as whitespace
+        assert_eq!(t.get_state(), 9);
+        assert!(t.match_string("は"));
+        assert!(t.skip_char());
+        assert_eq!(t.get_substr(13, t.get_state()), "日");
+        assert!(t.match_regex(&TerminalRegex::new(r"\p{Han}*の").unwrap()));
+        assert!(t.match_string("首都です"));
+        assert!(t.is_empty());
+        assert!(!t.skip_char());
+    }
+
+    #[test]
+    #[should_panic]
+    fn state_inside_character() {
+        let mut t = CodeTokenizer::new("ä");
+        t.set_state(1);
+    }
+
+    #[test]
+    fn anchored_regex() {
+        let mut t = CodeTokenizer::new("ab cd 12");
+        // Only matches at the position, the code before it is context
+        assert!(!t.match_regex(&TerminalRegex::new(r"[0-9]+").unwrap()));
+        assert_eq!(t.get_state(), 0);
+        t.set_state(1);
+        assert!(!t.match_regex(&TerminalRegex::new(r"\bb").unwrap()));
+        assert!(t.match_regex(&TerminalRegex::new(r"\Bb").unwrap()));
+        assert!(t.match_regex(&TerminalRegex::new(r"cd|c").unwrap()));
+        assert_eq!(t.get_state(), 6);
+    }
+
+    #[test]
+    fn bytes() {
+        let mut t = CodeTokenizer::from_bytes(b"\x00\xff ab\x80\n");
+        assert!(!t.is_text());
+        assert!(t.match_bytes(b"\x00"));
+        assert!(!t.match_byte_range(0x00, 0xfe));
+        assert!(t.match_byte_range(0xf0, 0xff));
+        // Only ASCII whitespace is skipped
+        assert_eq!(t.get_state(), 3);
+        assert!(!t.match_regex(&TerminalRegex::new("ab").unwrap()));
+        assert!(t.match_bytes_regex(&TerminalRegex::new_bytes(r"(?-u)[a-z]+\x80").unwrap()));
+        assert!(t.is_empty());
+        assert_eq!(t.get_bytes(1, 2), b"\xff");
+        assert_eq!(t.line_column(5), (1, 6));
+        t.set_state(4);
+        assert!(t.skip_char());
+        assert!(!t.match_any_bytes(3));
+        assert!(t.match_any_bytes(2));
+        assert!(!t.skip_char());
+
+        // Bytes can't split a character of text
+        let mut t = CodeTokenizer::new("äb");
+        assert!(!t.match_bytes(b"\xc3"));
+        assert!(!t.match_any_bytes(1));
+        assert!(t.match_any_bytes(2));
+        assert!(t.match_byte_range(b'a', b'z'));
     }
 }
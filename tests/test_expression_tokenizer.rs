@@ -73,4 +73,45 @@ mod expression_tokenizer {
         );
         assert_eq!(tok.next_token().unwrap(), ExpressionToken::GroupEnd);
     }
+
+    #[test]
+    fn annotations() {
+        let mut tok = ExpressionTokenizer::new("@lexical [a-z]+");
+        assert_eq!(
+            tok.peek_token().unwrap(),
+            &ExpressionToken::Annotation("lexical".to_string())
+        );
+        assert_eq!(
+            tok.next_token().unwrap(),
+            ExpressionToken::Annotation("lexical".to_string())
+        );
+        assert_eq!(
+            tok.next_token().unwrap(),
+            ExpressionToken::TerminalRegexExpression("[a-z]".to_string())
+        );
+        assert_eq!(tok.next_token().unwrap(), ExpressionToken::OneOrMore);
+        assert!(tok.peek_token().is_none());
+    }
+
+    #[test]
+    fn labels() {
+        let mut tok = ExpressionTokenizer::new("lhs:Sum op: ('+'|'-')");
+        assert_eq!(
+            tok.next_token().unwrap(),
+            ExpressionToken::Label("lhs".to_string())
+        );
+        assert_eq!(
+            tok.next_token().unwrap(),
+            ExpressionToken::Expression("Sum".to_string())
+        );
+        assert_eq!(
+            tok.next_token().unwrap(),
+            ExpressionToken::Label("op".to_string())
+        );
+        assert_eq!(tok.next_token().unwrap(), ExpressionToken::GroupBegin);
+        assert_eq!(
+            tok.next_token().unwrap(),
+            ExpressionToken::TerminalExpression("+".to_string())
+        );
+    }
 }
@@ -0,0 +1,103 @@
+#[cfg(test)]
+mod find {
+    use red_peg::code_tokenizer::CodeTokenizer;
+    use red_peg::parser::*;
+    use red_peg::span::Span;
+    use red_peg::trace::Tracer;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Instant;
+
+    // Counts the parses, the start rule is entered once per parse
+    #[derive(Default)]
+    struct ParseCounter(usize);
+
+    impl Tracer for ParseCounter {
+        fn enter(&mut self, rule: &str, _expression: &str, _offset: usize) {
+            if rule.is_empty() {
+                self.0 += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn find_iter() {
+        let mut parser: Parser<(u32, u32, u32)> = Parser::new();
+        parser.add_rule_str(
+            "Date",
+            "@lexical Number '-' Number '-' Number",
+            Some(Box::new(|r, t: &CodeTokenizer| {
+                let number = |i: usize| r[i].text(t).parse().unwrap();
+                (number(0), number(2), number(4))
+            })),
+        );
+        parser.add_rule_str("Number", "[0-9]+", None);
+
+        let text = "Released 2020-01-31, fixed on 2020-2-3 and 1-2- ün 99-1-1";
+        let matches: Vec<_> = parser.find_iter("Date", text).collect();
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].span, Span::new(9, 19));
+        assert_eq!(matches[0].result, Some((2020, 1, 31)));
+        assert_eq!(matches[1].result, Some((2020, 2, 3)));
+        assert_eq!(&text[matches[2].span.range()], "99-1-1");
+
+        // Matches don't overlap
+        parser.add_rule_str("Pair", "[a-z] [a-z]", None);
+        let spans: Vec<_> = parser
+            .find_iter("Pair", "abc d")
+            .map(|m| (m.span, m.result))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![(Span::new(0, 2), None), (Span::new(2, 5), None)]
+        );
+        assert_eq!(parser.find_iter("Date", "").count(), 0);
+    }
+
+    #[test]
+    fn first_terminals() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Signed", "@lexical Sign? [0-9]+", None);
+        parser.add_rule_str("Sign", "'-' | '+'", None);
+        let text = "a -12 b+ 7 -";
+        let spans: Vec<_> = parser.find_iter("Signed", text).map(|m| m.span).collect();
+        assert_eq!(spans, vec![Span::new(2, 5), Span::new(9, 10)]);
+
+        // Only where Sign or the number matches
+        let counter = Rc::new(RefCell::new(ParseCounter::default()));
+        parser.set_tracer(Some(counter.clone()));
+        assert_eq!(parser.find_iter("Signed", text).count(), 2);
+        assert_eq!(counter.borrow().0, 4);
+        parser.set_tracer(None);
+
+        // Whatever a skipper rule skips is searched as well, like before
+        parser.add_rule_str("Comment", "'#' [a-z0-9 ]* '#'", None);
+        parser.set_skipper(Skipper::Rule("Comment".to_string()));
+        parser.add_rule_str("Number", "[0-9]+", None);
+        let spans: Vec<_> = parser
+            .find_iter("Number", "#1 a# 2")
+            .map(|m| m.span)
+            .collect();
+        assert_eq!(spans, vec![Span::new(1, 2), Span::new(6, 7)]);
+    }
+
+    #[test]
+    fn find_iter_in_linear_time() {
+        let short = "a".repeat(100_000) + " 1 let";
+        let long = "a".repeat(400_000) + " 1 let";
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Number", "[0-9]+", None);
+        parser.add_rule_str("Keyword", "'let'", None);
+        let time = |rule, text| {
+            (0..3)
+                .map(|_| {
+                    let start = Instant::now();
+                    assert_eq!(parser.find_iter(rule, text).count(), 1);
+                    start.elapsed().as_secs_f64()
+                })
+                .fold(f64::INFINITY, f64::min)
+        };
+        assert!(time("Number", &long) < 8.0 * time("Number", &short));
+        assert!(time("Keyword", &long) < 8.0 * time("Keyword", &short));
+    }
+}
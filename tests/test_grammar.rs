@@ -0,0 +1,115 @@
+#[cfg(test)]
+mod grammar {
+    use red_peg::code_tokenizer::CodeTokenizer;
+    use red_peg::error::GrammarErrorKind;
+    use red_peg::parser::*;
+
+    const CALCULATOR: &str = r"
+        # A calculator, whitespace is skipped between all terminals
+        Sum <- Product (('+' | '-') Product)*
+        Product -> Value
+            (('*' | '/') Value)*   # multiplication binds stronger
+        Value <- [\d]+ | '(' Sum ')'
+        Hash <- '#' [#]   # hashes inside of terminals are no comments
+    ";
+
+    #[test]
+    fn load_calculator() {
+        let mut parser: Parser<i32> = Parser::from_grammar_str(CALCULATOR).unwrap();
+        parser
+            .set_callback(
+                "Sum",
+                Some(Box::new(|r: ParsingResult<i32>, _t: &CodeTokenizer| {
+                    let mut sum = r[0].rule_result.unwrap();
+                    for v in &r[1].sub_results {
+                        match v[0].selected_choice.unwrap() {
+                            0 => sum += v[1].rule_result.unwrap(),
+                            _ => sum -= v[1].rule_result.unwrap(),
+                        }
+                    }
+                    sum
+                })),
+            )
+            .unwrap();
+        parser
+            .set_callback(
+                "Product",
+                Some(Box::new(|r: ParsingResult<i32>, _t: &CodeTokenizer| {
+                    let mut product = r[0].rule_result.unwrap();
+                    for v in &r[1].sub_results {
+                        match v[0].selected_choice.unwrap() {
+                            0 => product *= v[1].rule_result.unwrap(),
+                            _ => product /= v[1].rule_result.unwrap(),
+                        }
+                    }
+                    product
+                })),
+            )
+            .unwrap();
+        parser
+            .set_callback(
+                "Value",
+                Some(Box::new(|r: ParsingResult<i32>, t: &CodeTokenizer| match r
+                    .selected_choice
+                    .unwrap()
+                {
+                    0 => r.text(t).parse().unwrap(),
+                    _ => r[0][1].rule_result.unwrap(),
+                })),
+            )
+            .unwrap();
+
+        assert_eq!(parser.parse("Sum", "2 * (3 + 4) - 1").unwrap(), 13);
+        assert!(parser.validate("Hash", "##"));
+        assert!(!parser.validate("Hash", "#"));
+        assert_eq!(
+            parser.set_callback("Missing", None).unwrap_err().kind,
+            GrammarErrorKind::UndefinedRule
+        );
+    }
+
+    #[test]
+    fn load_grammar_errors() {
+        let err = Parser::<()>::from_grammar_str("A <- 'a'\nB -> ('b'\nC <- 'c'")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, GrammarErrorKind::UnbalancedParentheses);
+        assert_eq!(err.rule, "B");
+        assert_eq!(err.offset, 14);
+
+        let err = Parser::<()>::from_grammar_str("A <- 'a'\n  A <- 'b'")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, GrammarErrorKind::DuplicateRule);
+        assert_eq!(err.offset, 11);
+
+        let err = Parser::<()>::from_grammar_str("# header\n'a'\nA <- 'a'")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, GrammarErrorKind::ExpectedRuleDefinition);
+        assert_eq!(err.offset, 9);
+
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("A", "'a'", None);
+        parser.load_grammar("B <- A+ # comment\n").unwrap();
+        assert!(parser.validate("B", "a a"));
+
+        let err = Parser::<()>::from_grammar_str("A <- 'a'\nB <- 'b' @lexical")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.kind,
+            GrammarErrorKind::InvalidAnnotation("lexical".to_string())
+        );
+        assert_eq!(err.offset, 18);
+    }
+
+    #[test]
+    fn load_annotations() {
+        let parser: Parser<()> =
+            Parser::from_grammar_str("List <- Ident (',' Ident)*\nIdent <- @lexical [a-z]+ [0-9]*")
+                .unwrap();
+        assert!(parser.validate("List", "ab1 , c"));
+        assert!(!parser.validate("List", "ab 1"));
+    }
+}
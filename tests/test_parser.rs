@@ -1,7 +1,10 @@
 #[cfg(test)]
 mod parser {
     use red_peg::code_tokenizer::CodeTokenizer;
+    use red_peg::error::{GrammarErrorKind, ParseErrorKind};
+    use red_peg::input::Bytes;
     use red_peg::parser::*;
+    use std::time::Instant;
 
     #[test]
     fn stringify_choice_sequence_terminal() {
@@ -227,7 +230,7 @@ mod parser {
                         sum -= second_value;
                     }
                 }
-                return sum;
+                sum
             })),
         );
         broken_calculator.add_rule_str(
@@ -243,7 +246,7 @@ mod parser {
                         sum /= second_value;
                     }
                 }
-                return sum;
+                sum
             })),
         );
         broken_calculator.add_rule_str(
@@ -253,15 +256,11 @@ mod parser {
                 let choice = r.selected_choice.unwrap();
                 match choice {
                     0 => {
-                        let digit_str = t
-                            .get_substr(r.parsed_string_start, r.parsed_string_end)
-                            .trim();
+                        let digit_str = r.text(t);
                         let i: i32 = digit_str.parse().unwrap();
-                        return i;
-                    }
-                    1 => {
-                        return r[0][1].rule_result.unwrap();
+                        i
                     }
+                    1 => r[0][1].rule_result.unwrap(),
                     _ => {
                         unreachable!();
                     }
@@ -313,18 +312,27 @@ mod parser {
     }
 
     #[test]
-    #[should_panic]
     fn endless_loop() {
         let mut parser: Parser<()> = Parser::new();
         parser.add_rule_str("Start", "''*", None);
-        assert!(parser.parse("Start", "a b c def").is_err());
+        let err = parser.parse("Start", "a b c def").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::EndlessLoop(String::from("Start")));
+        assert_eq!(err.position, 0);
+
+        // Rejected before parsing, even if the input wouldn't reach the repetition
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Start", "'a' Empty+", None);
+        parser.add_rule_str("Empty", "'b'?", None);
+        let err = parser.parse("Start", "x").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::EndlessLoop(String::from("Start")));
+        assert!(!parser.validate("Start", "a"));
     }
     #[test]
-    #[should_panic]
     fn endless_loop2() {
         let mut parser: Parser<()> = Parser::new();
         parser.add_rule_str("Start", "''+", None);
-        assert!(parser.parse("Start", "a b c def").is_err());
+        let err = parser.parse("Start", "a b c def").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::EndlessLoop(String::from("Start")));
     }
 
     #[test]
@@ -408,4 +416,746 @@ mod parser {
         assert!(parser.validate("Start", "c d c d"));
         assert!(!parser.validate("Start", "c d c"));
     }
+
+    #[test]
+    fn packrat() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let calls = Rc::new(Cell::new(0));
+        let mut parser: Parser<i32> = Parser::new();
+        parser.add_rule_str(
+            "Start",
+            "Number 'x' | Number 'y' | Number 'z'",
+            Some(Box::new(|r: ParsingResult<i32>, _t: &CodeTokenizer| {
+                r[0][0].rule_result.unwrap()
+            })),
+        );
+        let calls_cpy = calls.clone();
+        parser.add_rule_str(
+            "Number",
+            r"[\d]+",
+            Some(Box::new(move |r: ParsingResult<i32>, t: &CodeTokenizer| {
+                calls_cpy.set(calls_cpy.get() + 1);
+                r.text(t).parse().unwrap()
+            })),
+        );
+
+        assert_eq!(parser.parse("Start", "42 z").unwrap(), 42);
+        assert_eq!(calls.get(), 3);
+
+        calls.set(0);
+        parser.set_packrat(true);
+        assert_eq!(parser.parse("Start", "42 z").unwrap(), 42);
+        assert_eq!(calls.get(), 1);
+        assert!(parser.validate("Start", "7 y"));
+        assert!(!parser.validate("Start", "7 w"));
+
+        parser.set_packrat(false);
+        calls.set(0);
+        assert_eq!(parser.parse("Start", "42 z").unwrap(), 42);
+        assert_eq!(calls.get(), 3);
+    }
+
+    fn left_recursive_calculator() -> Parser<'static, i32> {
+        let mut parser: Parser<i32> = Parser::new();
+        parser.add_rule_str(
+            "Sum",
+            "Sum '+' Product | Sum '-' Product | Product",
+            Some(Box::new(|r: ParsingResult<i32>, _t: &CodeTokenizer| {
+                let v = &r[0];
+                match r.selected_choice.unwrap() {
+                    0 => v[0].rule_result.unwrap() + v[2].rule_result.unwrap(),
+                    1 => v[0].rule_result.unwrap() - v[2].rule_result.unwrap(),
+                    _ => v.rule_result.unwrap(),
+                }
+            })),
+        );
+        parser.add_rule_str(
+            "Product",
+            "Product '*' Value | Product '/' Value | Value",
+            Some(Box::new(|r: ParsingResult<i32>, _t: &CodeTokenizer| {
+                let v = &r[0];
+                match r.selected_choice.unwrap() {
+                    0 => v[0].rule_result.unwrap() * v[2].rule_result.unwrap(),
+                    1 => v[0].rule_result.unwrap() / v[2].rule_result.unwrap(),
+                    _ => v.rule_result.unwrap(),
+                }
+            })),
+        );
+        parser.add_rule_str(
+            "Value",
+            r"[\d]+",
+            Some(Box::new(|r: ParsingResult<i32>, t: &CodeTokenizer| {
+                r.text(t).parse().unwrap()
+            })),
+        );
+        parser
+    }
+
+    #[test]
+    fn left_recursion() {
+        let mut parser = left_recursive_calculator();
+        parser.set_left_recursion(true);
+        assert_eq!(parser.parse("Sum", "7").unwrap(), 7);
+        assert_eq!(parser.parse("Sum", "10 - 3 - 2").unwrap(), 5);
+        assert_eq!(parser.parse("Sum", "2 * 3 + 4 * 5").unwrap(), 26);
+        assert_eq!(parser.parse("Sum", "100 / 10 / 5 - 1").unwrap(), 1);
+        assert!(parser.parse("Sum", "1 -").is_err());
+        assert!(!parser.validate("Sum", "- 1"));
+
+        parser.set_packrat(true);
+        assert_eq!(parser.parse("Sum", "10 - 3 - 2").unwrap(), 5);
+        assert_eq!(parser.parse("Sum", "2 * 3 + 4 * 5").unwrap(), 26);
+    }
+
+    #[test]
+    fn indirect_left_recursion() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("List", "Items | 'x'", None);
+        parser.add_rule_str("Items", "List ',' 'x'", None);
+        parser.set_left_recursion(true);
+        assert!(parser.validate("List", "x"));
+        assert!(parser.validate("List", "x, x, x"));
+        assert!(!parser.validate("List", "x, x,"));
+
+        parser.set_packrat(true);
+        assert!(parser.validate("List", "x, x, x"));
+        assert!(!parser.validate("List", "x x"));
+    }
+
+    #[test]
+    fn dump_groups() {
+        let mut parser: Parser<()> = Parser::new();
+        let rule = "!('a' 'b') pair:('a' 'b')? ('a' | 'b')+ 'c'";
+        parser.add_rule_str("Start", rule, None);
+        assert_eq!(parser.to_string(), format!("Start -> {}", rule));
+    }
+
+    #[test]
+    fn left_recursion_disabled() {
+        let parser = left_recursive_calculator();
+        let err = parser.parse("Sum", "1 + 2").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::LeftRecursion(String::from("Sum")));
+        assert_eq!(err.position, 0);
+        assert!(!parser.validate("Sum", "1 + 2"));
+        assert!(parser.parse_tree("Sum", "1 + 2").is_err());
+    }
+
+    #[test]
+    fn undefined_rules() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Start", "'a' (Missing | 'b')", None);
+        let err = parser.parse("Start", "a b").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::UndefinedRule(String::from("Missing"))
+        );
+        assert_eq!(err.position, 2);
+        let err = parser.parse("Other", "a b").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::UndefinedRule(String::from("Other"))
+        );
+
+        parser.add_rule_str("Plain", "'a' 'b'", None);
+        parser.set_skipper(Skipper::Rule("Spacing".to_string()));
+        let err = parser.parse("Plain", "a b").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::UndefinedRule(String::from("Spacing"))
+        );
+        assert!(parser.parse_tree("Plain", "a b").is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let mut parser = left_recursive_calculator();
+        parser.set_left_recursion(true);
+
+        let err = parser.parse("Sum", "2 * 4 -").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnparsedInput);
+        assert_eq!(err.position, 7);
+        assert_eq!(err.expected, vec!["Product".to_string()]);
+        assert_eq!((err.line, err.column), (1, 8));
+
+        let err = parser.parse("Sum", "1 +\n2 +\n  * 3").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 3));
+        assert_eq!(err.source_line, "  * 3");
+        assert_eq!(
+            format!("{}", err),
+            "the input was not parsed completely at line 3, column 3, expected Product\n  |\n3 |   * 3\n  |   ^"
+        );
+
+        let err = parser.parse("Sum", "x").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::NoMatch);
+        assert_eq!(err.expected, vec!["Sum".to_string()]);
+
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Start", "'a' ('b' | 'c' | !'x' 'e')", None);
+        let err = parser.parse("Start", "a d").unwrap_err();
+        assert_eq!(err.position, 2);
+        assert_eq!(err.expected, vec!["'b'", "'c'", "'e'"]);
+        assert_eq!(
+            parser.parse("Start", "a b").unwrap_err().kind,
+            ParseErrorKind::MissingCallback("Start".to_string())
+        );
+    }
+
+    #[test]
+    fn unicode_input() {
+        let mut parser: Parser<Vec<String>> = Parser::new();
+        parser.add_rule_str(
+            "Words",
+            "Word+ '。'?",
+            Some(Box::new(
+                |r: ParsingResult<Vec<String>>, t: &CodeTokenizer| {
+                    r[0].sub_results
+                        .iter()
+                        .map(|w| w.text(t).to_string())
+                        .collect()
+                },
+            )),
+        );
+        parser.add_rule_str("Word", "@lexical [\\p{L}ー]+", None);
+
+        assert_eq!(
+            parser.parse("Words", "Schöne Grüße\taus München ").unwrap(),
+            vec!["Schöne", "Grüße", "aus", "München"]
+        );
+        assert_eq!(
+            parser.parse("Words", "こんにちは\u{3000}世界。").unwrap(),
+            vec!["こんにちは", "世界"]
+        );
+
+        let err = parser.parse("Words", "ÄÖÜ äöü 1").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnparsedInput);
+        assert_eq!(err.position, 14);
+        assert_eq!((err.line, err.column), (1, 9));
+        let err = parser.parse("Words", "東京\n大阪、").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+        assert_eq!(err.source_line, "大阪、");
+    }
+
+    #[test]
+    fn borrowed_results() {
+        let input = String::from("name = Grüße, city = 東京");
+        let mut parser: Parser<Vec<&str>> = Parser::new();
+        parser.add_rule_str(
+            "Pairs",
+            "Pair (',' Pair)*",
+            Some(Box::new(|r, _t| {
+                let mut pairs = r[0].rule_result.clone().unwrap();
+                for pair in &r[1].sub_results {
+                    pairs.extend(pair[1].rule_result.clone().unwrap());
+                }
+                pairs
+            })),
+        );
+        parser.add_rule_str(
+            "Pair",
+            "Word '=' Word",
+            Some(Box::new(|r, t| vec![r[0].text(t), r[2].text(t)])),
+        );
+        parser.add_rule_str("Word", "@lexical [^\\s,=]+", None);
+
+        let pairs = parser.parse("Pairs", &input).unwrap();
+        assert_eq!(pairs, vec!["name", "Grüße", "city", "東京"]);
+        // The results point into the input, nothing was copied
+        assert_eq!(pairs[1].as_ptr(), input[7..].as_ptr());
+        assert_eq!(pairs[3].as_ptr(), input[input.len() - 6..].as_ptr());
+    }
+
+    // The fastest of a few runs, in seconds
+    fn fastest_run(run: impl Fn()) -> f64 {
+        (0..3)
+            .map(|_| {
+                let start = Instant::now();
+                run();
+                start.elapsed().as_secs_f64()
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    #[test]
+    fn failing_regexes_in_linear_time() {
+        let short = "a ".repeat(10_000);
+        let long = "a ".repeat(40_000);
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Items", "Item*", None);
+        parser.add_rule_str("Item", "[0-9]+ | 'a'", None);
+        let time = |code| fastest_run(|| assert!(parser.validate("Items", code)));
+        // A regex that searched the rest of the input would take 16 times as long
+        assert!(time(&long) < 8.0 * time(&short));
+    }
+
+    fn frame_parser<'a>() -> Parser<'a, Vec<u32>, Bytes> {
+        let mut parser: Parser<Vec<u32>, Bytes> = Parser::new();
+        parser.set_skipper(Skipper::None);
+        parser.add_rule(
+            "Frame",
+            SequenceParsingExpression::new(vec![
+                TerminalParsingExpression::new_bytes(&[0xca, 0xfe]),
+                TerminalParsingExpression::new_byte_range(1, 2),
+                NonTerminalParsingExpression::new("Readings"),
+                TerminalParsingExpression::new_from_bytes_regex(r"(?-u)[^\x00]*\x00"),
+            ]),
+            Some(Box::new(|r, t| {
+                let mut values = vec![u32::from(r[1].bytes(t)[0])];
+                values.extend(r[2].rule_result.clone().unwrap());
+                values
+            })),
+        );
+        parser.add_rule_str(
+            "Readings",
+            "Count (Short | Long)*",
+            Some(Box::new(|r, t| {
+                let mut values = vec![IntegerFormat::U8.decode(r[0].bytes(t))];
+                for reading in &r[1].sub_results {
+                    values.push(match reading.selected_choice {
+                        Some(0) => IntegerFormat::U16Be.decode(&reading.bytes(t)[1..]),
+                        _ => IntegerFormat::U32Le.decode(&reading.bytes(t)[1..]),
+                    });
+                }
+                values
+            })),
+        );
+        parser.add_rule(
+            "Count",
+            TerminalParsingExpression::new_integer(IntegerFormat::U8),
+            None,
+        );
+        parser.add_rule_str("Short", "'s' U16", None);
+        parser.add_rule_str("Long", "'l' U32", None);
+        parser.add_rule(
+            "U16",
+            TerminalParsingExpression::new_integer(IntegerFormat::U16Be),
+            None,
+        );
+        parser.add_rule(
+            "U32",
+            TerminalParsingExpression::new_integer(IntegerFormat::U32Le),
+            None,
+        );
+        parser
+    }
+
+    #[test]
+    fn byte_parsing() {
+        let frame = b"\xca\xfe\x02\x02s\x01\x02l\x04\x03\x02\x01\xff\x00";
+        let mut frames = frame.to_vec();
+        frames.extend_from_slice(b"\xca\xfe\x01\x00\x00");
+        let parser = frame_parser();
+        assert_eq!(
+            parser.parse_bytes("Frame", frame).unwrap(),
+            vec![2, 2, 0x0102, 0x0102_0304]
+        );
+
+        let (first, end) = parser.parse_bytes_prefix("Frame", &frames).unwrap();
+        assert_eq!((first.len(), end), (4, frame.len()));
+        assert_eq!(
+            parser.parse_bytes_prefix("Frame", &frames[end..]).unwrap(),
+            (vec![1, 0], 5)
+        );
+
+        let err = parser
+            .parse_bytes("Frame", b"\xca\xfe\x01\x01s\x01")
+            .unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::NoMatch);
+        assert_eq!((err.position, err.line, err.column), (5, 1, 6));
+        assert_eq!(err.expected, vec!["U16"]);
+        let err = parser.parse_bytes("Frame", b"\xca\xfe\x03").unwrap_err();
+        assert_eq!(err.expected, vec!["b[\\x01-\\x02]"]);
+        let err = parser.parse_bytes("Frame", b"\xca\xfd").unwrap_err();
+        assert_eq!(err.expected, vec!["Frame"]);
+        assert_eq!(
+            TerminalParsingExpression::<()>::new_bytes(b"\xca\xfe").dump(),
+            "b'\\xca\\xfe'"
+        );
+        assert_eq!(
+            TerminalParsingExpression::<()>::new_integer(IntegerFormat::U16Be).dump(),
+            "u16be"
+        );
+
+        // Text terminals match their UTF-8 bytes, whitespace is skipped if the skipper says so
+        let mut parser: Parser<(), Bytes> = Parser::new();
+        parser.add_rule_str("Greeting", "'grüß' 'dich'", Some(Box::new(|_r, _t| ())));
+        assert!(parser
+            .parse_bytes("Greeting", "grüß \n dich ".as_bytes())
+            .is_ok());
+        parser.set_skipper(Skipper::None);
+        assert!(parser
+            .parse_bytes("Greeting", "grüß dich".as_bytes())
+            .is_err());
+
+        // Regexes of grammar strings match bytes
+        let mut parser: Parser<u32, Bytes> = Parser::new();
+        parser.add_rule_str(
+            "Number",
+            "[0-9]+ 'x'",
+            Some(Box::new(|r, t| {
+                std::str::from_utf8(r[0].bytes(t)).unwrap().parse().unwrap()
+            })),
+        );
+        assert_eq!(parser.parse_bytes("Number", b"123x").unwrap(), 123);
+        assert_eq!(parser.parse_bytes("Number", b"4 x").unwrap(), 4);
+        assert!(parser.parse_bytes("Number", b"x").is_err());
+    }
+
+    #[test]
+    fn grammar_errors() {
+        let mut parser: Parser<()> = Parser::new();
+        let kind_at = |parser: &mut Parser<()>, right_side: &str| {
+            let err = parser
+                .try_add_rule_str("Start", right_side, None)
+                .unwrap_err();
+            assert_eq!(err.rule, "Start");
+            (err.kind, err.offset)
+        };
+        assert_eq!(
+            kind_at(&mut parser, "('a' 'b'"),
+            (GrammarErrorKind::UnbalancedParentheses, 0)
+        );
+        assert_eq!(
+            kind_at(&mut parser, "'a' 'b')"),
+            (GrammarErrorKind::UnbalancedParentheses, 7)
+        );
+        assert_eq!(
+            kind_at(&mut parser, "'a' (*'b')"),
+            (GrammarErrorKind::DanglingQuantifier, 5)
+        );
+        assert_eq!(
+            kind_at(&mut parser, "'a' | 'b' !"),
+            (GrammarErrorKind::DanglingPredicate, 10)
+        );
+        assert_eq!(
+            kind_at(&mut parser, "'a' | | 'b'"),
+            (GrammarErrorKind::EmptyAlternative, 6)
+        );
+        assert_eq!(
+            kind_at(&mut parser, "'a' ()"),
+            (GrammarErrorKind::EmptyAlternative, 5)
+        );
+        assert_eq!(
+            kind_at(&mut parser, ""),
+            (GrammarErrorKind::EmptyAlternative, 0)
+        );
+        assert_eq!(
+            kind_at(&mut parser, "'a' 'b"),
+            (GrammarErrorKind::UnterminatedTerminal, 4)
+        );
+        match kind_at(&mut parser, "'a' [a-z") {
+            (GrammarErrorKind::UnterminatedTerminal, 4) => {}
+            other => panic!("{:?}", other),
+        }
+        match kind_at(&mut parser, "'a' [\\p{Foo}]") {
+            (GrammarErrorKind::InvalidRegex(message), 4) => assert!(!message.is_empty()),
+            other => panic!("{:?}", other),
+        }
+
+        assert!(parser.try_add_rule_str("Start", "'a'", None).is_ok());
+        let err = parser.try_add_rule_str("Start", "'b'", None).unwrap_err();
+        assert_eq!(err.kind, GrammarErrorKind::DuplicateRule);
+        assert_eq!(format!("{}", err), "rule Start is already defined");
+        assert!(parser.validate("Start", "a"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_grammar() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Start", "* 'a'", None);
+    }
+
+    #[test]
+    fn skippers() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Start", "'a' 'b'", None);
+        parser.add_rule_str(
+            "Spacing",
+            r"([\s] | '//' [^\n]* | '/*' (!'*/' [\s\S])* '*/')*",
+            None,
+        );
+        assert!(parser.validate("Start", " a b "));
+        assert!(!parser.validate("Start", "a // comment\nb"));
+
+        parser.set_skipper(Skipper::None);
+        assert!(parser.validate("Start", "ab"));
+        assert!(!parser.validate("Start", "a b"));
+        assert!(!parser.validate("Start", "ab "));
+
+        parser.set_skipper(Skipper::Rule("Spacing".to_string()));
+        assert!(parser.validate("Start", "a // comment\n /* more\n comment */ b "));
+        assert!(parser.validate("Start", "/**/a/**/b"));
+        assert!(!parser.validate("Start", "a / b"));
+        let err = parser.parse("Start", "a /* */ c").unwrap_err();
+        assert_eq!(err.position, 8);
+        assert_eq!(err.expected, vec!["'b'".to_string()]);
+    }
+
+    #[test]
+    fn lexical_rules() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("List", "Ident (',' Ident)*", None);
+        parser.add_rule_str("Ident", "[a-z] [a-z0-9]*", None);
+        parser.add_rule_str("Spacing", "[\\s]*", None);
+        assert!(parser.validate("List", "a b c"));
+
+        parser.set_lexical("Ident", true).unwrap();
+        assert!(parser.validate("List", " ab , c1,d "));
+        assert!(!parser.validate("List", "a b c"));
+        assert!(!parser.validate("List", "a, 1b"));
+
+        parser.set_skipper(Skipper::Rule("Spacing".to_string()));
+        assert!(parser.validate("List", " ab , c1,d "));
+        assert!(!parser.validate("List", "a b c"));
+
+        assert_eq!(
+            parser.set_lexical("Missing", true).unwrap_err().kind,
+            GrammarErrorKind::UndefinedRule
+        );
+    }
+
+    #[test]
+    fn lexical_annotation() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("List", "Ident (',' Ident)*", None);
+        parser.add_rule_str("Ident", "@lexical [a-z] [a-z0-9]*", None);
+        assert!(parser.validate("List", " ab , c1,d "));
+        assert!(!parser.validate("List", "a b c"));
+
+        let err = parser
+            .try_add_rule_str("Number", "@lexical [0-9] @lexical [0-9]*", None)
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            GrammarErrorKind::InvalidAnnotation("lexical".to_string())
+        );
+        assert_eq!(err.offset, 15);
+
+        let err = parser
+            .try_add_rule_str("Number", "@compact [0-9]+", None)
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            GrammarErrorKind::InvalidAnnotation("compact".to_string())
+        );
+        assert_eq!(
+            err.to_string(),
+            "invalid annotation @compact in rule Number at offset 0"
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let mut parser: Parser<i32> = Parser::new();
+        parser.add_rule_str(
+            "Sum",
+            "lhs:Product (op:('+' | '-') rhs:Product)*",
+            Some(Box::new(|r: ParsingResult<i32>, _t: &CodeTokenizer| {
+                let mut sum = r.get("lhs").unwrap().rule_result.unwrap();
+                let ops = r.all("op");
+                for (op, rhs) in ops.iter().zip(r.all("rhs")) {
+                    match op.selected_choice.unwrap() {
+                        0 => sum += rhs.rule_result.unwrap(),
+                        _ => sum -= rhs.rule_result.unwrap(),
+                    }
+                }
+                sum
+            })),
+        );
+        parser.add_rule_str(
+            "Product",
+            "lhs:Value ('*' rhs:Value)*",
+            Some(Box::new(|r: ParsingResult<i32>, _t: &CodeTokenizer| {
+                r.all("rhs")
+                    .iter()
+                    .fold(r.get("lhs").unwrap().rule_result.unwrap(), |product, v| {
+                        product * v.rule_result.unwrap()
+                    })
+            })),
+        );
+        parser.add_rule_str(
+            "Value",
+            "number:[0-9]+ | '(' sum:Sum ')'",
+            Some(Box::new(
+                |r: ParsingResult<i32>, t: &CodeTokenizer| match r.get("number") {
+                    Some(number) => number.text(t).parse().unwrap(),
+                    None => r.get("sum").unwrap().rule_result.unwrap(),
+                },
+            )),
+        );
+        assert_eq!(parser.parse("Sum", "2 * (3 + 4) - 1 * 5").unwrap(), 9);
+        assert_eq!(parser.parse("Sum", "7").unwrap(), 7);
+
+        let err = parser
+            .try_add_rule_str("Broken", "'a' x: | 'b'", None)
+            .unwrap_err();
+        assert_eq!(err.kind, GrammarErrorKind::InvalidLabel);
+        assert_eq!(err.offset, 7);
+        let err = parser
+            .try_add_rule_str("Broken", "'a' :'b'", None)
+            .unwrap_err();
+        assert_eq!(err.kind, GrammarErrorKind::InvalidLabel);
+        assert_eq!(err.offset, 4);
+    }
+
+    #[test]
+    fn cut() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Block", "'{' Statement* '}'", None);
+        parser.add_rule_str(
+            "Statement",
+            "'if' ~ '(' Name ')' Statement | Name '=' Name ';'",
+            None,
+        );
+        parser.add_rule_str("Name", "@lexical [a-z]+", None);
+        assert!(parser.validate("Block", "{ if (a) b = c; d = e; }"));
+
+        // 'if' commits to the first alternative, it is no name anymore
+        assert!(!parser.validate("Block", "{ if = a; }"));
+        let err = parser
+            .parse_tree("Block", "{ a = b; if x = y; }")
+            .unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::NoMatch);
+        assert_eq!(err.column, 13);
+        assert_eq!(err.expected, vec!["'('"]);
+
+        let err = parser
+            .try_add_rule_str("Broken", "'a' ~*", None)
+            .unwrap_err();
+        assert_eq!(err.kind, GrammarErrorKind::DanglingQuantifier);
+        let err = parser
+            .try_add_rule_str("Broken", "'a' !~ 'b'", None)
+            .unwrap_err();
+        assert_eq!(err.kind, GrammarErrorKind::DanglingPredicate);
+    }
+
+    #[test]
+    fn cut_recovery() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Statements", "Statement*", None);
+        parser.add_rule_str(
+            "Statement",
+            "@recover(';') 'let' ~ Name '=' Name ';' | Name ';'",
+            None,
+        );
+        parser.add_rule_str("Name", "@lexical [a-z]+", None);
+        let (tree, errors) = parser.parse_tree_recovering("Statements", "let a = b; let ; c;");
+        assert_eq!(tree.unwrap().children.len(), 3);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].column, 16);
+        assert_eq!(errors[0].expected, vec!["Name"]);
+    }
+
+    #[test]
+    fn parse_prefix() {
+        let mut parser: Parser<String> = Parser::new();
+        parser.add_rule_str(
+            "Header",
+            "'#' Name",
+            Some(Box::new(|r: ParsingResult<String>, t: &CodeTokenizer| {
+                r[1].text(t).to_string()
+            })),
+        );
+        parser.add_rule_str("Name", "@lexical [a-z]+", None);
+
+        let code = "#title \n#intro text";
+        let (name, consumed) = parser.parse_prefix("Header", code).unwrap();
+        assert_eq!(name, "title");
+        assert_eq!(consumed, 8);
+        let (name, end) = parser.parse_at("Header", code, consumed).unwrap();
+        assert_eq!(name, "intro");
+        assert_eq!(end, 15);
+        assert!(parser.parse("Header", code).is_err());
+
+        let err = parser.parse_at("Header", code, end).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::NoMatch);
+        assert_eq!((err.line, err.column), (2, 8));
+        assert_eq!(err.expected, vec!["Header"]);
+
+        parser.add_rule_str("Word", "Name", None);
+        let err = parser.parse_prefix("Word", "abc").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::MissingCallback("Word".to_string())
+        );
+
+        let err = parser.parse_at("Header", "a", 5).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::InvalidOffset(5));
+        assert_eq!((err.position, err.column), (1, 2));
+        let err = parser.parse_at("Header", "#ä", 2).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::InvalidOffset(2));
+        assert_eq!((err.position, err.column), (1, 2));
+    }
+
+    // Pushes states it never pops and leaves the position behind when it fails
+    struct SloppyExpression {
+        succeed: bool,
+    }
+
+    impl ParsingExpression<i32> for SloppyExpression {
+        fn matches(&self, info: &mut ParsingInformation<i32>) -> Option<ParsingResult<i32>> {
+            let start = info.tokenizer().push_state();
+            info.tokenizer().push_state();
+            if !info.tokenizer().match_string("x") || !self.succeed {
+                return None;
+            }
+            let end = info.tokenizer().get_state();
+            Some(ParsingResult::new(start, end))
+        }
+    }
+
+    #[test]
+    fn unbalanced_states() {
+        let mut parser: Parser<i32> = Parser::new();
+        parser.add_rule(
+            "Start",
+            ChoiceParsingExpression::new(vec![
+                SequenceParsingExpression::new(vec![
+                    Box::new(SloppyExpression { succeed: false }),
+                    TerminalParsingExpression::new("y"),
+                ]),
+                SequenceParsingExpression::new(vec![
+                    Box::new(SloppyExpression { succeed: true }),
+                    TerminalParsingExpression::new("z"),
+                ]),
+            ]),
+            Some(Box::new(|r, _t| r.selected_choice.unwrap() as i32)),
+        );
+        assert_eq!(parser.parse("Start", "xz").unwrap(), 1);
+        assert!(parser.validate("Start", "xz"));
+        let err = parser.parse("Start", "xy").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::NoMatch);
+        assert_eq!(err.position, 1);
+    }
+
+    #[test]
+    fn checkpoints() {
+        struct Pair;
+        impl ParsingExpression<i32> for Pair {
+            fn matches(&self, info: &mut ParsingInformation<i32>) -> Option<ParsingResult<i32>> {
+                let mut checkpoint = info.checkpoint();
+                let first =
+                    checkpoint.match_expression(TerminalParsingExpression::new("a").as_ref())?;
+                checkpoint.match_expression(TerminalParsingExpression::new("b").as_ref())?;
+                let start = checkpoint.start();
+                Some(
+                    ParsingResult::new(start, checkpoint.commit())
+                        .with_tokens(first.token_start, first.token_end),
+                )
+            }
+        }
+        let mut parser: Parser<i32> = Parser::new();
+        parser.add_rule(
+            "Start",
+            ChoiceParsingExpression::new(vec![
+                Box::new(Pair),
+                TerminalParsingExpression::new("a c"),
+            ]),
+            Some(Box::new(|r, _t| r.selected_choice.unwrap() as i32)),
+        );
+        assert_eq!(parser.parse("Start", "a b").unwrap(), 0);
+        assert_eq!(parser.parse("Start", "a c").unwrap(), 1);
+    }
 }
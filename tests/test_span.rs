@@ -0,0 +1,87 @@
+#[cfg(test)]
+mod span {
+    use red_peg::code_tokenizer::CodeTokenizer;
+    use red_peg::parser::*;
+    use red_peg::span::Span;
+
+    #[test]
+    fn line_columns() {
+        let tokenizer = CodeTokenizer::new("ab\r\nc\n\nüd");
+        let span = Span::new(1, 9);
+        assert_eq!(span.len(), 8);
+        assert_eq!(span.start_line_column(&tokenizer), (1, 2));
+        assert_eq!(span.end_line_column(&tokenizer), (4, 2));
+        assert_eq!(Span::new(4, 4).start_line_column(&tokenizer), (2, 1));
+        assert_eq!(Span::new(6, 6).end_line_column(&tokenizer), (3, 1));
+        assert_eq!(tokenizer.line_at(2), "ab");
+        assert_eq!(tokenizer.line_at(6), "");
+        assert_eq!(tokenizer.line_at(100), "üd");
+    }
+
+    #[test]
+    fn spans_in_callbacks() {
+        let mut parser: Parser<Vec<(usize, usize)>> = Parser::new();
+        parser.add_rule_str(
+            "Words",
+            "Word+",
+            Some(Box::new(|r, t: &CodeTokenizer| {
+                r.flatten()
+                    .iter()
+                    .map(|word| word.span().start_line_column(t))
+                    .collect()
+            })),
+        );
+        parser.add_rule_str("Word", "@lexical [a-z]+", None);
+        assert_eq!(
+            parser.parse("Words", "one two\n  three\nfour").unwrap(),
+            vec![(1, 1), (1, 5), (2, 3), (3, 1)]
+        );
+    }
+
+    #[test]
+    fn token_spans_and_trivia() {
+        let mut parser: Parser<(Span, Span, Span, String)> = Parser::new();
+        parser.add_rule_str(
+            "Call",
+            "Name '(' ')'",
+            Some(Box::new(|r, t: &CodeTokenizer| {
+                (
+                    r.leading_trivia(),
+                    r.span(),
+                    r.trailing_trivia(),
+                    r[0].text(t).to_string(),
+                )
+            })),
+        );
+        parser.add_rule_str("Name", "@lexical [a-z]+", None);
+        let (leading, span, trailing, name) = parser.parse("Call", "  foo ( )\n").unwrap();
+        assert_eq!(leading, Span::new(0, 2));
+        assert_eq!(span, Span::new(2, 9));
+        assert_eq!(trailing, Span::new(9, 10));
+        assert_eq!(name, "foo");
+    }
+
+    #[test]
+    fn predicates_are_empty() {
+        let mut parser: Parser<(Span, Span, Span, String)> = Parser::new();
+        parser.add_rule_str(
+            "Start",
+            "'x' &'y' !'z'",
+            Some(Box::new(|r, t: &CodeTokenizer| {
+                assert_eq!(r[2].full_span(), Span::new(2, 2));
+                (
+                    r.span(),
+                    r.trailing_trivia(),
+                    r[1].span(),
+                    r.text(t).to_string(),
+                )
+            })),
+        );
+        let (span, trailing, lookahead, text) = parser.parse_prefix("Start", "x y").unwrap().0;
+        assert_eq!(span, Span::new(0, 1));
+        assert_eq!(trailing, Span::new(1, 2));
+        assert_eq!(trailing.len(), 1);
+        assert_eq!(lookahead, Span::new(2, 2));
+        assert_eq!(text, "x");
+    }
+}
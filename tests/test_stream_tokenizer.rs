@@ -0,0 +1,214 @@
+#[cfg(test)]
+mod stream_tokenizer {
+    use red_peg::error::ParseErrorKind;
+    use red_peg::input::{Stream, TerminalRegex};
+    use red_peg::parser::{Parser, Skipper};
+    use red_peg::stream_tokenizer::StreamTokenizer;
+    use std::cell::Cell;
+    use std::io;
+    use std::io::{BufReader, Read};
+    use std::rc::Rc;
+
+    // Reads at most size bytes at a time
+    fn chunked(text: &str, size: usize) -> BufReader<&[u8]> {
+        BufReader::with_capacity(size, text.as_bytes())
+    }
+
+    #[test]
+    fn chunk_boundaries() {
+        let mut t = StreamTokenizer::new(chunked("Hallo   Welt!\ngrüße 42\n", 3));
+        t.set_skip_whitespace(false);
+        assert!(t.match_string("Hallo"));
+        assert!(!t.match_string("Welt"));
+        t.skip_whitespaces();
+        assert!(t.match_string("Welt!"));
+        assert_eq!(t.get_state(), 13);
+        t.skip_whitespaces();
+        assert!(t.match_regex(&TerminalRegex::new(r"\w+").unwrap()));
+        assert_eq!(t.get_state(), 21);
+        assert_eq!(t.get_substr(14, 21), Some("grüße"));
+        assert_eq!(t.line_column(21), (2, 6));
+        assert_eq!(t.line_at(21), "grüße 42");
+        assert!(t.skip_char());
+        assert!(t.match_regex(&TerminalRegex::new(r"[0-9]+\s*").unwrap()));
+        assert!(t.is_empty());
+        assert!(!t.skip_char());
+    }
+
+    #[test]
+    fn regex_across_lines() {
+        let mut t = StreamTokenizer::new(chunked("a\nb\nc\nd", 2));
+        t.set_skip_whitespace(false);
+        assert!(t.match_regex(&TerminalRegex::new(r"[a-c\n]+").unwrap()));
+        assert_eq!(t.get_state(), 6);
+        assert!(!t.match_regex(&TerminalRegex::new(r"d\n").unwrap()));
+        assert!(t.match_regex(&TerminalRegex::new(r"d$").unwrap()));
+        assert!(t.is_empty());
+
+        // Regexes that only match with the following lines
+        let mut t = StreamTokenizer::new(chunked("ab\ncd", 2));
+        assert!(t.match_regex(&TerminalRegex::new(r"ab\ncd").unwrap()));
+        assert!(t.is_empty());
+        let mut t = StreamTokenizer::new(chunked("/* a\n b */ c", 2));
+        assert!(t.match_regex(&TerminalRegex::new(r"/\*[^*]*\*/").unwrap()));
+        assert!(t.match_string("c"));
+        let mut t = StreamTokenizer::new(chunked("a\nb\n", 2));
+        assert!(!t.match_regex(&TerminalRegex::new(r"a\nc").unwrap()));
+        assert_eq!(t.get_state(), 0);
+        assert!(t.match_bytes_regex(&TerminalRegex::new_bytes(r"a\nb").unwrap()));
+        let mut t = StreamTokenizer::new(chunked("ab\nc", 2));
+        assert!(t.match_regex(&TerminalRegex::new(r"ab\nc|a").unwrap()));
+        assert!(t.is_empty());
+
+        // A regex that can't match anymore doesn't read further
+        let text = "line\n".repeat(1000);
+        let mut t = StreamTokenizer::new(chunked(&text, 16));
+        assert!(!t.match_regex(&TerminalRegex::new(r"line\d").unwrap()));
+        assert!(t.match_regex(&TerminalRegex::new(r"[a-z]+").unwrap()));
+        assert!(t.bytes_read() < 64);
+    }
+
+    #[test]
+    fn discarding() {
+        let text = "line\n".repeat(1000);
+        let mut t = StreamTokenizer::new(chunked(&text, 16));
+        t.set_skip_whitespace(false);
+        for _ in 0..500 {
+            assert!(t.match_string("line\n"));
+        }
+        assert!(t.buffered() < 64);
+        assert_eq!(t.line_column(2500), (501, 1));
+
+        // Nothing after a held position is discarded
+        t.hold(2500);
+        for _ in 0..500 {
+            assert!(t.match_string("line\n"));
+        }
+        assert!(t.is_empty());
+        assert!(t.buffered() >= 2500);
+        t.set_state(2500);
+        assert!(t.match_string("line\n"));
+        t.release(2500);
+    }
+
+    #[test]
+    fn read_discarded() {
+        let text = "line\n".repeat(100);
+        let mut t = StreamTokenizer::new(chunked(&text, 16));
+        while t.match_string("line") {}
+        t.set_state(0);
+        assert!(!t.match_string("line"));
+        t.skip_whitespaces();
+        assert_eq!(t.get_state(), 0);
+        assert_eq!(t.get_substr(0, 4), None);
+        assert_eq!(t.get_substr(495, 499), Some("line"));
+    }
+
+    fn log_parser<'a>(max_buffered: Rc<Cell<usize>>) -> Parser<'a, usize, Stream> {
+        let mut parser = Parser::new();
+        parser.set_skipper(Skipper::None);
+        parser.add_rule_str(
+            "Log",
+            "(line:Line)*",
+            Some(Box::new(|r, _t| {
+                r.all("line")
+                    .iter()
+                    .filter_map(|line| line.rule_result)
+                    .sum()
+            })),
+        );
+        parser.add_rule_str(
+            "Line",
+            "level:Level ' ' [^\\n]* [\\n]",
+            Some(Box::new(move |r, t: &StreamTokenizer| {
+                max_buffered.set(max_buffered.get().max(t.buffered()));
+                assert!(r.streamed_text(t).unwrap().ends_with('\n'));
+                r.get("level").unwrap().rule_result.unwrap()
+            })),
+        );
+        parser.add_rule_str(
+            "Level",
+            "'WARNING' | 'WARN' | 'INFO'",
+            Some(Box::new(|r, t| {
+                usize::from(r.streamed_text(t).unwrap().starts_with("WARN"))
+            })),
+        );
+        parser
+    }
+
+    #[test]
+    fn parse_reader() {
+        let mut text = String::new();
+        for i in 0..10_000 {
+            match i % 3 {
+                0 => text.push_str("INFO started the service\n"),
+                1 => text.push_str("WARNING disk almost full\n"),
+                _ => text.push_str("WARN retrying\n"),
+            }
+        }
+        let max_buffered = Rc::new(Cell::new(0));
+        let parser = log_parser(Rc::clone(&max_buffered));
+        assert_eq!(parser.parse_reader("Log", chunked(&text, 8)), Ok(6_666));
+        assert!(max_buffered.get() < 256);
+
+        let err = parser
+            .parse_reader("Log", chunked("INFO a\nWARN b\nINFOx c\n", 4))
+            .unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnparsedInput);
+        assert_eq!((err.line, err.column), (3, 5));
+        assert_eq!(err.source_line, "INFOx c");
+        assert_eq!(err.expected, vec!["' '"]);
+    }
+
+    #[test]
+    fn failure_after_discarding() {
+        let text = "alpha\nbeta\ngamma\ndelta\n";
+        let mut parser: Parser<Option<String>, Stream> = Parser::new();
+        parser.add_rule_str("Start", "Item Item Item Item 'never'", None);
+        parser.add_rule_str("Item", "[a-z]+", None);
+        let err = parser.parse_reader("Start", chunked(text, 4)).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::NoMatch);
+        assert_eq!((err.line, err.column), (5, 1));
+        assert_eq!(err.expected, vec!["Item"]);
+
+        // The text of a rule is kept for its callback
+        parser.add_rule_str("Word", "@lexical [a-z]+", None);
+        parser.add_rule_str(
+            "Text",
+            "Word Word Word Word",
+            Some(Box::new(|r, t: &StreamTokenizer| {
+                r.streamed_text(t).map(String::from)
+            })),
+        );
+        assert_eq!(
+            parser.parse_reader("Text", chunked(text, 4)),
+            Ok(Some(String::from("alpha\nbeta\ngamma\ndelta")))
+        );
+    }
+
+    struct FailingReader(usize);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::other("connection lost"));
+            }
+            self.0 -= 1;
+            buf[..6].copy_from_slice(b"INFO \n");
+            Ok(6)
+        }
+    }
+
+    #[test]
+    fn read_errors() {
+        let parser = log_parser(Rc::new(Cell::new(0)));
+        let err = parser
+            .parse_reader("Log", BufReader::new(FailingReader(2)))
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::Io(String::from("connection lost"))
+        );
+        assert_eq!((err.position, err.line, err.column), (12, 3, 1));
+    }
+}
@@ -0,0 +1,112 @@
+#[cfg(test)]
+mod token_stream {
+    use red_peg::error::{GrammarErrorKind, ParseErrorKind};
+    use red_peg::input::{Tokenizer, Tokens};
+    use red_peg::parser::{Parser, TokenParsingExpression};
+    use red_peg::token_stream::TokenStream;
+
+    #[derive(Debug, PartialEq)]
+    enum Token {
+        Ident(&'static str),
+        Number(i64),
+        Plus,
+        Assign,
+    }
+
+    fn assignment_parser<'a>() -> Parser<'a, i64, Tokens<Token>> {
+        let mut parser = Parser::new();
+        parser.add_rule(
+            "Ident",
+            TokenParsingExpression::new("identifier", |t| matches!(t, Token::Ident(_))),
+            Some(Box::new(|_r, _t| 0)),
+        );
+        parser.add_rule(
+            "Number",
+            TokenParsingExpression::new("number", |t| matches!(t, Token::Number(_))),
+            Some(Box::new(|r, t| match r.tokens(t) {
+                [Token::Number(n)] => *n,
+                _ => unreachable!(),
+            })),
+        );
+        parser.add_rule(
+            "Plus",
+            TokenParsingExpression::new("'+'", |t| *t == Token::Plus),
+            None,
+        );
+        parser.add_rule(
+            "Assign",
+            TokenParsingExpression::new("'='", |t| *t == Token::Assign),
+            None,
+        );
+        parser.add_rule_str(
+            "Sum",
+            "n:Number (Plus n:Number)*",
+            Some(Box::new(|r, _t| {
+                r.all("n").iter().filter_map(|n| n.rule_result).sum()
+            })),
+        );
+        parser.add_rule_str(
+            "Assignment",
+            "Ident Assign value:Sum",
+            Some(Box::new(|r, _t| {
+                r.get("value").unwrap().rule_result.unwrap()
+            })),
+        );
+        parser
+    }
+
+    #[test]
+    fn match_tokens() {
+        let tokens = [Token::Ident("x"), Token::Assign, Token::Number(4)];
+        let mut t = TokenStream::new(&tokens);
+        assert!(!t.match_token(&|t: &Token| *t == Token::Assign));
+        assert!(t.match_token(&|t: &Token| matches!(t, Token::Ident(_))));
+        assert!(t.skip_char());
+        assert_eq!(t.get_state(), 2);
+        assert_eq!(t.get_tokens(0, 2), &tokens[..2]);
+        assert!(t.skip_char());
+        assert!(t.is_empty());
+        assert!(!t.skip_char());
+        t.set_state(1);
+        assert!(!t.is_empty());
+    }
+
+    #[test]
+    fn parse_tokens() {
+        let tokens = [
+            Token::Ident("x"),
+            Token::Assign,
+            Token::Number(1),
+            Token::Plus,
+            Token::Number(2),
+        ];
+        let parser = assignment_parser();
+        assert_eq!(parser.parse_tokens("Assignment", &tokens), Ok(3));
+        assert_eq!(parser.parse_tokens_prefix("Sum", &tokens[2..]), Ok((3, 3)));
+
+        let err = parser.parse_tokens("Assignment", &tokens[..4]).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnparsedInput);
+        assert_eq!((err.position, err.line, err.column), (4, 1, 5));
+        assert_eq!(err.expected, vec!["Number"]);
+        let err = parser
+            .parse_tokens("Assignment", &[Token::Ident("x"), Token::Plus])
+            .unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::NoMatch);
+        assert_eq!(err.position, 1);
+        assert_eq!(err.expected, vec!["Assign"]);
+    }
+
+    #[test]
+    fn terminals_are_unsupported() {
+        let mut parser: Parser<(), Tokens<Token>> = Parser::new();
+        let err = parser
+            .try_add_rule_str("Assignment", "Ident '=' Sum", None)
+            .unwrap_err();
+        assert_eq!(err.kind, GrammarErrorKind::UnsupportedTerminal);
+        assert_eq!(err.rule, "Assignment");
+        let err = parser
+            .try_add_rule_str("Number", "[0-9]+", None)
+            .unwrap_err();
+        assert_eq!(err.kind, GrammarErrorKind::UnsupportedTerminal);
+    }
+}
@@ -0,0 +1,99 @@
+#[cfg(test)]
+mod trace {
+    use red_peg::parser::*;
+    use red_peg::span::Span;
+    use red_peg::trace::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct Events {
+        events: Vec<String>,
+    }
+
+    impl Tracer for Events {
+        fn enter(&mut self, rule: &str, expression: &str, offset: usize) {
+            self.events
+                .push(format!("enter {} {} {}", rule, expression, offset));
+        }
+        fn success(&mut self, rule: &str, expression: &str, span: Span) {
+            self.events.push(format!(
+                "success {} {} {}..{}",
+                rule, expression, span.start, span.end
+            ));
+        }
+        fn failure(&mut self, rule: &str, expression: &str, offset: usize) {
+            self.events
+                .push(format!("failure {} {} {}", rule, expression, offset));
+        }
+        fn exit(&mut self, rule: &str, expression: &str, offset: usize) {
+            self.events
+                .push(format!("exit {} {} {}", rule, expression, offset));
+        }
+    }
+
+    #[test]
+    fn tracer_hooks() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("Start", "'a' | 'b'", None);
+        let events = Rc::new(RefCell::new(Events::default()));
+        parser.set_tracer(Some(events.clone()));
+        assert!(parser.validate("Start", "b"));
+        assert_eq!(
+            events.borrow().events,
+            vec![
+                "enter  Start 0",
+                "enter Start ('a' | 'b') 0",
+                "enter Start 'a' 0",
+                "failure Start 'a' 0",
+                "exit Start 'a' 0",
+                "enter Start 'b' 0",
+                "success Start 'b' 0..1",
+                "exit Start 'b' 1",
+                "success Start ('a' | 'b') 0..1",
+                "exit Start ('a' | 'b') 1",
+                "success  Start 0..1",
+                "exit  Start 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn trace_printer() {
+        let mut parser: Parser<()> = Parser::new();
+        parser.add_rule_str("List", "Item (',' Item)*", None);
+        parser.add_rule_str("Item", "[a-z]", None);
+        parser.add_rule_str("Spacing", "[ ]*", None);
+        parser.set_skipper(Skipper::Rule("Spacing".to_string()));
+        let printer = Rc::new(RefCell::new(TracePrinter::new()));
+        parser.set_tracer(Some(printer.clone()));
+        assert!(!parser.validate("List", "a, 1"));
+        assert_eq!(
+            printer.borrow().trace(),
+            "List at 0
+  Item (',' Item)* at 0
+    Item at 0
+      [a-z] at 0
+      => matched 0..1
+    => matched 0..1
+    (',' Item)* at 1
+      ',' Item at 1
+        ',' at 1
+        => matched 1..2
+        Item at 3
+          [a-z] at 3
+          => failed
+        => failed
+      => failed
+    => matched 1..1
+  => matched 0..1
+=> matched 0..1
+"
+        );
+
+        printer.borrow_mut().clear();
+        parser.set_tracer(None);
+        assert!(parser.validate("List", "a"));
+        assert!(printer.borrow().trace().is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+#[cfg(test)]
+mod tree {
+    use red_peg::code_tokenizer::CodeTokenizer;
+    use red_peg::error::{GrammarErrorKind, ParseErrorKind};
+    use red_peg::parser::*;
+
+    fn calculator() -> Parser<'static, i32> {
+        let mut parser: Parser<i32> = Parser::new();
+        parser.add_rule_str("Sum", "Product (('+' | '-') Product)*", None);
+        parser.add_rule_str(
+            "Product",
+            "Value (('*' | '/') Value)*",
+            Some(Box::new(|_r: ParsingResult<i32>, _t: &CodeTokenizer| {
+                panic!("callbacks are not called for syntax trees")
+            })),
+        );
+        parser.add_rule_str("Value", "Number | '(' Sum ')'", None);
+        parser.add_rule_str("Number", "[0-9]+", None);
+        parser
+    }
+
+    #[test]
+    fn parse_tree() {
+        let parser = calculator();
+        let code = "1 + 2*3";
+        let tree = parser.parse_tree("Sum", code).unwrap();
+        assert_eq!(tree.rule, "Sum");
+        assert_eq!(tree.span.range(), 0..7);
+        assert_eq!(tree.text(code), "1 + 2*3");
+        assert_eq!(tree.children.len(), 2);
+        assert_eq!(tree.children[0].text(code), "1");
+
+        let product = &tree.children[1];
+        assert_eq!(product.rule, "Product");
+        assert_eq!(product.text(code), "2*3");
+        let rules: Vec<_> = product
+            .descendants()
+            .iter()
+            .map(|node| node.rule.as_str())
+            .collect();
+        assert_eq!(rules, vec!["Product", "Value", "Number", "Value", "Number"]);
+        assert_eq!(product.children[1].children[0].text(code), "3");
+
+        let err = parser.parse_tree("Sum", "(1 + 2").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::NoMatch);
+        let err = parser.parse_tree("Sum", "1 + 2)").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnparsedInput);
+    }
+
+    #[test]
+    fn parse_tree_packrat_left_recursion() {
+        let mut parser: Parser<i32> = Parser::new();
+        parser.add_rule_str("Sum", "Sum '+' Number | Number", None);
+        parser.add_rule_str("Number", "[0-9]+ !'!' | [0-9]+ '!'", None);
+        parser.set_packrat(true);
+        parser.set_left_recursion(true);
+
+        let code = "1+2+3!";
+        let tree = parser.parse_tree("Sum", code).unwrap();
+        assert_eq!(tree.text(code), "1+2+3!");
+        assert_eq!(tree.children.len(), 2);
+        assert_eq!(tree.children[0].text(code), "1+2");
+        assert_eq!(tree.children[0].children[0].children[0].text(code), "1");
+        assert_eq!(tree.children[1].rule, "Number");
+        assert_eq!(tree.children[1].text(code), "3!");
+    }
+
+    #[test]
+    fn parse_ast() {
+        let parser: Parser<()> = Parser::from_grammar_str(
+            r"
+            Array <- '[' List? ']'
+            List <- @flatten Value (Comma List)?
+            Value <- @inline Number | Array
+            Number <- [0-9]+
+            Comma <- @drop ','
+            ",
+        )
+        .unwrap();
+        let code = "[1, [2], 3]";
+        let ast = parser.parse_ast("Array", code).unwrap();
+        assert_eq!(ast.kind, "Array");
+        let list = &ast.children[0];
+        assert_eq!(list.kind, "List");
+        let kinds: Vec<_> = list.children.iter().map(|n| n.kind.as_str()).collect();
+        assert_eq!(kinds, vec!["Number", "Array", "Number"]);
+        assert_eq!(list.children[2].text(code), "3");
+        assert_eq!(list.children[1].children[0].children[0].kind, "Number");
+
+        // The start rule is never inlined or dropped
+        let ast = parser.parse_ast("Value", "[]").unwrap();
+        assert_eq!(ast.kind, "Value");
+        assert_eq!(ast.children[0].kind, "Array");
+        assert!(ast.children[0].children.is_empty());
+    }
+
+    fn config() -> Parser<'static, ()> {
+        Parser::from_grammar_str(
+            r"
+            Config <- Section*
+            Section <- @recover('}') Name '{' Setting* '}'
+            Setting <- @recover(';') Name '=' Value ';'
+            Name <- @lexical [a-z]+
+            Value <- @lexical [0-9]+ | Name
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn recover_errors() {
+        let parser = config();
+        let code = "a { x = 1; y = ; z = 3; }\nb { w 4; }\nc { v = 5; } d {";
+        let (tree, errors) = parser.parse_tree_recovering("Config", code);
+        let tree = tree.unwrap();
+
+        let positions: Vec<_> = errors.iter().map(|e| (e.line, e.column)).collect();
+        assert_eq!(positions, vec![(1, 16), (2, 7), (3, 17)]);
+        assert_eq!(errors[0].expected, vec!["Value"]);
+        assert_eq!(errors[1].expected, vec!["'='"]);
+        assert_eq!(errors[2].kind, ParseErrorKind::UnparsedInput);
+
+        let sections: Vec<_> = tree.children.iter().map(|n| n.rule.as_str()).collect();
+        assert_eq!(sections, vec!["Section", "Section", "Section"]);
+        let settings = &tree.children[0].children;
+        assert_eq!(settings.len(), 4);
+        assert!(settings[1].error.is_none());
+        assert!(settings[2].error.is_some());
+        assert_eq!(settings[2].rule, "Setting");
+        assert_eq!(settings[2].text(code), "y = ;");
+        assert_eq!(tree.children[1].children[1].text(code), "w 4;");
+        assert_eq!(tree.errors().len(), 2);
+
+        // Without recovery the first error stops the parse
+        let err = parser.parse_tree("Config", code).unwrap_err();
+        assert_eq!((err.line, err.column), (1, 16));
+        let (tree, errors) = parser.parse_tree_recovering("Config", "a { x = 1; }");
+        assert!(tree.is_some());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn recover_annotation() {
+        let parser = config();
+        assert!(parser.to_string().contains("@recover(';')"));
+        let err = Parser::<()>::from_grammar_str("A <- @recover 'a'")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.kind,
+            GrammarErrorKind::InvalidAnnotation("recover".to_string())
+        );
+        let err = Parser::<()>::from_grammar_str("A <- @recover(';' 'a'")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, GrammarErrorKind::UnbalancedParentheses);
+    }
+}